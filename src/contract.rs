@@ -1,14 +1,19 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{ to_binary, Binary, Deps, DepsMut, Env, MessageInfo, BankMsg, Addr, Response, StdResult, SubMsg, WasmMsg};
+use cosmwasm_std::{ to_binary, Binary, Deps, DepsMut, Env, MessageInfo, BankMsg, Addr, Api, Response, StdError, StdResult, SubMsg, WasmMsg, Order, Storage, Timestamp, Uint128, coins, Event, Coin, CosmosMsg, from_binary};
 use cw2::set_contract_version;
+use cw_storage_plus::Bound;
+use bech32::FromBase32;
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
 use cw20::{Balance, Cw20ExecuteMsg, Cw20CoinVerified, Cw20ReceiveMsg, Cw20Coin};
+use cw_utils::{Duration, Expiration};
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, ReceiveMsg, GetCountResponse, InstantiateMsg, QueryMsg, CreateMsg, ListResponse, DetailsResponse};
-use crate::state::{State, STATE, MINIMAL_DONATION, GenericBalance, Escrow, ESCROWS, all_escrow_ids};
+use crate::msg::{ExecuteMsg, MigrateMsg, ReceiveMsg, GetCountResponse, InstantiateMsg, QueryMsg, CreateMsg, ListResponse, DetailsResponse, StreamMsg, StreamDetails, AmendmentsResponse, RecipientShareMsg, ContributorsResponse, Contribution, SubmissionsResponse, SealedClaim, SwapAsk, SignedApproval, NonceResponse, ResolverQueryMsg, ResolutionResponse, Resolution, ArbiterResponse, ArbitersResponse, StakeResponse, TemplateOverrides, TemplateResponse, TemplatesResponse, EscrowAction, BatchMode, BatchResponse, BatchResult, IbcFundingMsg, StatsResponse, KeeperResponse, SweepResponse, SettledResponse, SettledHistoryResponse};
+use crate::state::{State, STATE, MINIMAL_DONATION, GenericBalance, Escrow, ESCROWS, ExpiryMode, all_escrow_ids, Stream, Change, Amendment, PENDING_AMENDMENTS, AMENDMENTS, DurationLimits, DURATION_LIMITS, LEGACY_ESCROWS, RecipientShare, TOTAL_SHARES, CONTRIBUTIONS, Submission, SubmissionStatus, SubmissionLimits, SUBMISSIONS, OPEN_SUBMISSIONS, SUBMISSION_COUNTS, LAST_SUBMISSION, SUBMISSION_LIMITS, NONCES, ArbiterProfile, ARBITERS, ARBITER_STATS, RANKED_ARBITERS, REQUIRE_REGISTERED_ARBITER, StakingConfig, STAKING_CONFIG, STAKES, LOCKED_STAKES, TOTAL_STAKED, Slashable, SLASHABLE, Unbonding, UNBONDING, Decision, PendingDecision, Appeal, Consent, Template, TEMPLATES, REMOTE_FUNDERS, RETURNED_REFUNDS, IbcPayout, IbcTransfer, Stats, STATS, index_deadlines, unindex_deadlines, take_expired, count_expired, StorageCache, KEEPER_TIP, KEEPER_RESERVE, Payout, Settlement, SETTLED, SETTLED_BY_PARTY};
 
-use self::query::{query_list, query_detail};
+use self::query::{query_list, query_detail, query_amendments, query_contributors, query_submissions, query_arbiter, query_arbiters, query_ranked_arbiters, query_template, query_templates, query_stats, query_settled, query_settled_history};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:cw-otms";
@@ -48,13 +53,128 @@ pub fn execute(
         ExecuteMsg:: Donate {} => execute::donate(deps, info),
         ExecuteMsg:: Withdraw{} => execute::withdraw(deps, env, info),
         
-        ExecuteMsg:: Create(msg)=> {execute::execute_create(deps, msg, Balance::from(info.funds), &info.sender)},
+        ExecuteMsg:: Create(msg)=> {execute::execute_create(deps, env, msg, Balance::from(info.funds), &info.sender)},
         ExecuteMsg:: SetRecipient { id, recipient } => execute::execute_set_recipient(deps, env, info, id, recipient),
-        ExecuteMsg:: TopUp {id} => execute::execute_top_up(deps, id, Balance::from(info.funds)),
+        ExecuteMsg:: TopUp {id} => execute::execute_top_up(deps, id, Balance::from(info.funds), &info.sender),
+        ExecuteMsg:: CreateFromTemplate { template, id, version, overrides } => {
+            let balance = Balance::from(info.funds);
+            execute::execute_create_from_template(deps, env, template, id, version, overrides, balance, &info.sender)
+        }
+        ExecuteMsg:: RegisterTemplate { name, defaults } => execute::execute_register_template(deps, info, name, defaults),
+        ExecuteMsg:: RemoveTemplate { name } => execute::execute_remove_template(deps, info, name),
+        ExecuteMsg:: Batch { actions, mode } => execute::execute_batch(deps, env, info, actions, mode),
+        ExecuteMsg:: FundOverIbc { refund_to, action } => execute::execute_fund_over_ibc(deps, env, info, refund_to, action),
+        ExecuteMsg:: ClaimRefund { id } => execute::execute_claim_refund(deps, env, info, id),
+        ExecuteMsg:: Cancel { id } => execute::execute_cancel(deps, env, info, id),
         ExecuteMsg:: Approve {id} => execute::execute_approve(deps, id, env, info),
+        ExecuteMsg:: ApproveWithSignature { id, signature, pubkey, nonce } => {
+            execute::execute_approve_with_signature(deps, env, id, signature, pubkey, nonce)
+        }
         ExecuteMsg:: Refund { id } => execute::execute_refund(deps, env, info, id),
-        ExecuteMsg:: Receive(msg) => execute:: execute_receive(deps, info, msg)
+        ExecuteMsg:: Receive(msg) => execute:: execute_receive(deps, env, info, msg),
+        ExecuteMsg:: Disburse { id } => execute::execute_disburse(deps, env, id),
+        ExecuteMsg:: CancelStream { id } => execute::execute_cancel_stream(deps, env, info, id),
+        ExecuteMsg:: Amend { id, title, description, expires, cw20_whitelist } => {
+            execute::execute_amend(deps, env, info, id, title, description, expires, cw20_whitelist)
+        }
+        ExecuteMsg:: AcceptAmendment { id } => execute::execute_accept_amendment(deps, env, info, id),
+        ExecuteMsg:: RejectAmendment { id } => execute::execute_reject_amendment(deps, info, id),
+        ExecuteMsg:: SetRecipients { id, recipients, dust_recipient } => {
+            execute::execute_set_recipients(deps, env, info, id, recipients, dust_recipient)
+        }
+        ExecuteMsg:: AcceptRole { id } => execute::execute_accept_role(deps, info, id),
+        ExecuteMsg:: SubmitClaim { id, link, hunter } => execute::execute_submit_claim(deps, env, info, id, link, hunter),
+        ExecuteMsg:: CommitClaim { id, commitment } => execute::execute_commit_claim(deps, env, info, id, commitment),
+        ExecuteMsg:: RevealClaim { id, submission_id, link, hunter, salt } => {
+            execute::execute_reveal_claim(deps, env, info, id, submission_id, link, hunter, salt)
+        }
+        ExecuteMsg:: Release { id, preimage } => execute::execute_release(deps, env, info, id, preimage),
+        ExecuteMsg:: FillSwap { id } => {
+            execute::execute_fill_swap(deps, env, id, Balance::from(info.funds), &info.sender)
+        }
+        ExecuteMsg:: Resolve { id } => execute::execute_resolve(deps, env, id),
+        ExecuteMsg:: TakeOverArbitration { id } => execute::execute_take_over_arbitration(deps, env, info, id),
+        ExecuteMsg:: NominateArbiter { id, arbiter } => execute::execute_nominate_arbiter(deps, env, info, id, arbiter),
+        ExecuteMsg:: Appeal { id } => execute::execute_appeal(deps, env, info, id),
+        ExecuteMsg:: DecideAppeal { id, approve } => execute::execute_decide_appeal(deps, env, info, id, approve),
+        ExecuteMsg:: FinalizeDecision { id } => execute::execute_finalize_decision(deps, env, info, id),
+        ExecuteMsg:: MutualApprove { id } => execute::execute_mutual(deps, env, info, id, Decision::Approve),
+        ExecuteMsg:: MutualRefund { id } => execute::execute_mutual(deps, env, info, id, Decision::Refund),
+        ExecuteMsg:: PickWinner { id, submission_id } => execute::execute_pick_winner(deps, env, info, id, submission_id),
+        ExecuteMsg:: RejectSubmission { id, submission_id } => {
+            execute::execute_reject_submission(deps, env, info, id, submission_id)
+        }
+        ExecuteMsg:: SetSubmissionLimits { cooldown_blocks, max_per_bounty } => {
+            let limits = SubmissionLimits { cooldown_blocks, max_per_bounty };
+            execute::execute_set_submission_limits(deps, info, limits)
+        }
+        ExecuteMsg:: RegisterArbiter { name, contact_hash, fee_terms } => {
+            let profile = ArbiterProfile { name, contact_hash, fee_terms };
+            execute::execute_register_arbiter(deps, info, profile)
+        }
+        ExecuteMsg:: UnregisterArbiter {} => execute::execute_unregister_arbiter(deps, info),
+        ExecuteMsg:: SetArbiterPolicy { require_registered } => {
+            execute::execute_set_arbiter_policy(deps, info, require_registered)
+        }
+        ExecuteMsg:: Stake {} => execute::execute_stake(deps, info),
+        ExecuteMsg:: Unbond { amount } => execute::execute_unbond(deps, env, info, amount),
+        ExecuteMsg:: ClaimUnbonded {} => execute::execute_claim_unbonded(deps, env, info),
+        ExecuteMsg:: Slash { arbiter, id, amount } => execute::execute_slash(deps, info, arbiter, id, amount),
+        ExecuteMsg:: SetStakingConfig { denom, min_stake_bps, unbonding_period, governance } => {
+            execute::execute_set_staking_config(deps, info, denom, min_stake_bps, unbonding_period, governance)
+        }
+        ExecuteMsg:: SweepExpired { limit } => execute::execute_sweep_expired(deps, env, info, limit),
+        ExecuteMsg:: FundKeeperReserve {} => execute::execute_fund_keeper_reserve(deps, info),
+        ExecuteMsg:: SetKeeperTip { tip } => execute::execute_set_keeper_tip(deps, info, tip),
+        ExecuteMsg:: SetDurationLimits { min_blocks, max_blocks, min_seconds, max_seconds } => {
+            let limits = DurationLimits { min_blocks, max_blocks, min_seconds, max_seconds };
+            execute::execute_set_duration_limits(deps, info, limits)
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    //escrows stored with end_height/end_time are rewritten with expirations
+    let mut migrated = 0u64;
+    for id in all_escrow_ids(deps.storage)? {
+        if let Ok(legacy) = LEGACY_ESCROWS.load(deps.storage, &id) {
+            ESCROWS.save(deps.storage, &id, &legacy.into())?;
+            migrated += 1;
+        }
+    }
+    //pending amendments refer to the old deadlines
+    PENDING_AMENDMENTS.clear(deps.storage);
+
+    //the ranking index is rebuilt from the registered arbiters
+    RANKED_ARBITERS.clear(deps.storage);
+    let arbiters: Vec<Addr> = ARBITERS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    for arbiter in &arbiters {
+        let stats = ARBITER_STATS.may_load(deps.storage, arbiter)?.unwrap_or_default();
+        let (decisions, speed) = stats.rank();
+        RANKED_ARBITERS.save(deps.storage, (decisions, speed, arbiter), &())?;
+    }
+
+    //the running totals and the expiry index start out with the escrows already open
+    if STATS.may_load(deps.storage)?.is_none() {
+        let mut stats = Stats::default();
+        for id in all_escrow_ids(deps.storage)? {
+            let escrow = ESCROWS.load(deps.storage, &id)?;
+            index_deadlines(deps.storage, &id, &escrow.expires)?;
+            stats.open += 1;
+            stats.created += 1;
+            stats.lock(&escrow.balance);
+        }
+        STATS.save(deps.storage, &stats)?;
     }
+
+    Ok(Response::new()
+        .add_attribute("method", "migrate")
+        .add_attribute("migrated", migrated.to_string()))
 }
 
 pub mod execute {
@@ -96,6 +216,7 @@ pub mod execute {
             };
             STATE.save(deps.storage, &temp)?;
         }
+        update_stats(deps.storage, |stats| stats.donated.add_tokens(Balance::from(info.funds)))?;
 
         Ok(Response::new().add_attribute("action", "donate"))
     }
@@ -121,6 +242,7 @@ pub mod execute {
 
     pub fn execute_create(
         deps: DepsMut,
+        env: Env,
         msg: CreateMsg,
         balance: Balance,
         sender: &Addr,
@@ -128,6 +250,7 @@ pub mod execute {
         if balance.is_empty(){
             return Err(ContractError::EmptyBalance{});
         }
+        let expires = resolve_deadlines(deps.storage, &env, &msg)?;
         let mut cw20_whitelist = msg.addr_whitelist(deps.api)?;
         let escrow_balance = match balance {
             Balance::Native(balance) => GenericBalance {
@@ -146,43 +269,290 @@ pub mod execute {
             }
         };
 
-        let recipient: Option<Addr> = msg.recipient.and_then(|addr|deps.api.addr_validate(&addr).ok());
-        let escrow = Escrow {
-            arbiter: deps.api.addr_validate(&msg.arbiter)?,
+        let arbiter = deps.api.addr_validate(&msg.arbiter)?;
+        if REQUIRE_REGISTERED_ARBITER.may_load(deps.storage)?.unwrap_or_default()
+            && !ARBITERS.has(deps.storage, &arbiter)
+        {
+            return Err(ContractError::ArbiterNotRegistered {});
+        }
+        let recipient: Option<Addr> = msg.recipient.map(|addr|deps.api.addr_validate(&addr)).transpose()?;
+        let (splits, dust_recipient) = match msg.recipients {
+            Some(_) if recipient.is_some() => return Err(ContractError::ConflictingRecipients {}),
+            Some(recipients) => {
+                let (splits, dust_recipient) = build_shares(deps.api, recipients, msg.dust_recipient)?;
+                (splits, Some(dust_recipient))
+            }
+            None => (vec![], None),
+        };
+        let stream = match msg.stream {
+            Some(stream) => Some(build_stream(deps.as_ref(), &env, stream)?),
+            None => None,
+        };
+        //a hashlock is a SHA-256 hash and needs a timelock to refund the source. Nobody can
+        //set the recipient later, so it is fixed up front
+        if let Some(hashlock) = &msg.hashlock {
+            let no_recipient = recipient.is_none() && splits.is_empty() && msg.ibc_payout.is_none();
+            if hashlock.len() != 32 || expires.is_empty() || no_recipient {
+                return Err(ContractError::InvalidHashlock {});
+            }
+        }
+        let hashlock = msg.hashlock;
+        let appeal_arbiter = msg.appeal_arbiter.map(|addr| deps.api.addr_validate(&addr)).transpose()?;
+        if msg.challenge_period.is_some() && appeal_arbiter.is_none() {
+            return Err(ContractError::NoAppealArbiter {});
+        }
+        let ask = match msg.ask {
+            Some(ask) => Some(build_ask(deps.api, ask, &expires)?),
+            None => None,
+        };
+        let ibc_payout = match msg.ibc_payout {
+            Some(_) if recipient.is_some() || !splits.is_empty() => return Err(ContractError::ConflictingRecipients {}),
+            //ICS-20 transfers carry native coins only
+            Some(payout) if !cw20_whitelist.is_empty() || payout.channel_id.is_empty() || payout.receiver.is_empty() => {
+                return Err(ContractError::InvalidIbcPayout {})
+            }
+            payout => payout,
+        };
+        let deposit = escrow_balance.clone();
+        let mut escrow = Escrow {
+            arbiter,
             recipient,
             source: sender.clone(),
             title: msg.title,
             description: msg.description,
-            end_height: msg.end_height,
-            end_time: msg.end_time,
+            expires,
+            expiry_mode: msg.expiry_mode.unwrap_or_default(),
             balance: escrow_balance,
             cw20_whitelist,
+            stream,
+            require_acceptance: msg.require_acceptance,
+            recipient_accepted: false,
+            splits,
+            dust_recipient,
+            bounty: msg.bounty,
+            sealed: msg.sealed,
+            reveal_start: msg.reveal_start,
+            hashlock,
+            ask,
+            resolver: msg.resolver,
+            created_at: Some(env.block.time),
+            last_arbiter_action: env.block.height,
+            inactivity_window: msg.inactivity_window,
+            backup_arbiter: msg.backup_arbiter.map(|addr| deps.api.addr_validate(&addr)).transpose()?,
+            source_nominee: None,
+            recipient_nominee: None,
+            challenge_period: msg.challenge_period,
+            appeal_arbiter,
+            appeal_bond: msg.appeal_bond,
+            pending_decision: None,
+            consents: vec![],
+            lock_in_until: msg.lock_in.map(|lock_in| lock_in.after(&env.block)),
+            ibc_payout,
+            locked_stake: Uint128::zero(),
         };
 
-        // try to store it, fail if the id was already in use
-        ESCROWS.update(deps.storage, &msg.id, |existing| match existing {
-            None => Ok(escrow),
-            Some(_) => Err(ContractError::AlreadyInUse {}),
+        // fail if the id was already in use, settled ids stay taken so their settlement is kept
+        if ESCROWS.has(deps.storage, &msg.id) || SETTLED.has(deps.storage, &msg.id) {
+            return Err(ContractError::AlreadyInUse {});
+        }
+        lock_stake(deps.storage, &mut escrow)?;
+        let deadlines = escrow.expires.clone();
+        ESCROWS.save(deps.storage, &msg.id, &escrow)?;
+        CONTRIBUTIONS.save(deps.storage, (&msg.id, sender), &deposit)?;
+        index_deadlines(deps.storage, &msg.id, &deadlines)?;
+        update_stats(deps.storage, |stats| {
+            stats.open += 1;
+            stats.created += 1;
+            stats.lock(&deposit);
         })?;
 
         let res = Response::new().add_attributes(vec![("action", "create"), ("id", msg.id.as_str())]);
         Ok(res)
     }
 
+    //swaps are filled in one message, so the ask is either native coins or a single cw20 token
+    fn build_ask(api: &dyn Api, ask: SwapAsk, expires: &[Expiration]) -> Result<GenericBalance, ContractError> {
+        let cw20 = ask
+            .cw20
+            .into_iter()
+            .map(|c| Ok(Cw20CoinVerified { address: api.addr_validate(&c.address)?, amount: c.amount }))
+            .collect::<StdResult<Vec<_>>>()?;
+        let ask = GenericBalance { native: ask.native, cw20 };
+        let fillable = ask.cw20.is_empty() || (ask.native.is_empty() && ask.cw20.len() == 1);
+        if ask.is_empty() || !fillable || expires.is_empty() {
+            return Err(ContractError::InvalidAsk {});
+        }
+        Ok(ask)
+    }
+
+    //turns relative deadlines into expirations and checks them against the configured limits
+    fn resolve_deadlines(
+        storage: &dyn Storage,
+        env: &Env,
+        msg: &CreateMsg,
+    ) -> Result<Vec<Expiration>, ContractError> {
+        let relative = msg
+            .expires_in
+            .iter()
+            .flatten()
+            .map(|d| deadline_after(env, d))
+            .collect::<Result<Vec<_>, _>>()?;
+        let expires: Vec<Expiration> = msg
+            .expires
+            .iter()
+            .flatten()
+            .copied()
+            .chain(relative)
+            .filter(|e| e != &Expiration::Never {})
+            .collect();
+        check_deadlines(env, &expires)?;
+        check_duration_limits(storage, env, &expires, msg.expiry_mode.unwrap_or_default())?;
+        Ok(expires)
+    }
+
+    //Duration::after adds unchecked, a far duration must not wrap around
+    fn deadline_after(env: &Env, duration: &Duration) -> Result<Expiration, ContractError> {
+        match *duration {
+            Duration::Height(blocks) => env.block.height.checked_add(blocks).map(Expiration::AtHeight),
+            Duration::Time(seconds) => seconds
+                .checked_mul(1_000_000_000)
+                .and_then(|nanos| env.block.time.nanos().checked_add(nanos))
+                .map(|nanos| Expiration::AtTime(Timestamp::from_nanos(nanos))),
+        }
+        .ok_or(ContractError::DeadlineOverflow {})
+    }
+
+    //with a maximum in place, the deadline that ends the escrow has to fall under one
+    fn check_duration_limits(
+        storage: &dyn Storage,
+        env: &Env,
+        expires: &[Expiration],
+        mode: ExpiryMode,
+    ) -> Result<(), ContractError> {
+        let limits = DURATION_LIMITS.may_load(storage)?.unwrap_or_default();
+        let mut bounded = vec![];
+        for expiration in expires {
+            let max = match expiration {
+                Expiration::AtHeight(height) => {
+                    check_duration(height - env.block.height, limits.min_blocks, limits.max_blocks)?;
+                    limits.max_blocks
+                }
+                Expiration::AtTime(time) => {
+                    check_duration(
+                        time.seconds().saturating_sub(env.block.time.seconds()),
+                        limits.min_seconds,
+                        limits.max_seconds,
+                    )?;
+                    limits.max_seconds
+                }
+                Expiration::Never {} => None,
+            };
+            bounded.push(max.is_some());
+        }
+        //any expires with the earliest deadline, all only with the latest
+        let bounded = match mode {
+            ExpiryMode::Any => bounded.iter().any(|b| *b),
+            ExpiryMode::All => !bounded.is_empty() && bounded.iter().all(|b| *b),
+        };
+        if limits.has_max() && !bounded {
+            return Err(ContractError::DurationTooLong {});
+        }
+        Ok(())
+    }
+
+    //deadlines must lie ahead, with at most one per kind
+    fn check_deadlines(env: &Env, expires: &[Expiration]) -> Result<(), ContractError> {
+        if expires.iter().any(|e| e.is_expired(&env.block)) {
+            return Err(ContractError::DeadlineInPast {});
+        }
+        let heights = expires.iter().filter(|e| matches!(e, Expiration::AtHeight(_))).count();
+        let times = expires.iter().filter(|e| matches!(e, Expiration::AtTime(_))).count();
+        if heights > 1 || times > 1 {
+            return Err(ContractError::ConflictingDeadlines {});
+        }
+        Ok(())
+    }
+
+    fn check_duration(duration: u64, min: Option<u64>, max: Option<u64>) -> Result<(), ContractError> {
+        if min.is_some_and(|min| duration < min) {
+            return Err(ContractError::DurationTooShort {});
+        }
+        if max.is_some_and(|max| duration > max) {
+            return Err(ContractError::DurationTooLong {});
+        }
+        Ok(())
+    }
+
+    pub fn execute_set_duration_limits(
+        deps: DepsMut,
+        info: MessageInfo,
+        limits: DurationLimits,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if info.sender != state.owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        DURATION_LIMITS.save(deps.storage, &limits)?;
+
+        Ok(Response::new().add_attribute("action", "set_duration_limits"))
+    }
+
+    fn build_stream(deps: Deps, env: &Env, msg: StreamMsg) -> Result<Stream, ContractError> {
+        let last_disbursed = match msg.period {
+            Duration::Height(0) | Duration::Time(0) => return Err(ContractError::InvalidPeriod {}),
+            Duration::Height(_) => env.block.height,
+            Duration::Time(_) => env.block.time.seconds(),
+        };
+        let cw20: StdResult<Vec<_>> = msg
+            .cw20
+            .into_iter()
+            .map(|c| {
+                Ok(Cw20CoinVerified {
+                    address: deps.api.addr_validate(&c.address)?,
+                    amount: c.amount,
+                })
+            })
+            .collect();
+        let amount = GenericBalance { native: msg.native, cw20: cw20? };
+        if amount.is_empty() {
+            return Err(ContractError::EmptyBalance {});
+        }
+
+        Ok(Stream {
+            amount,
+            period: msg.period,
+            recipient: deps.api.addr_validate(&msg.recipient)?,
+            last_disbursed,
+        })
+    }
+
     pub fn execute_set_recipient(
         deps: DepsMut,
-        _env: Env,
+        env: Env,
         info: MessageInfo,
         id: String,
         recipient: String,
     ) -> Result<Response, ContractError> {
         let mut escrow = ESCROWS.load(deps.storage, &id)?;
-        if info.sender != escrow.arbiter {
+        //the recipients of hash-locked and swap escrows are not up to the arbiter
+        if info.sender != escrow.arbiter || escrow.hashlock.is_some() || escrow.ask.is_some() {
             return Err(ContractError::Unauthorized {});
         }
+        if escrow.pending_decision.is_some() {
+            return Err(ContractError::DecisionPending {});
+        }
 
         let recipient = deps.api.addr_validate(recipient.as_str())?;
+        if escrow.recipient.as_ref() != Some(&recipient) {
+            escrow.recipient_accepted = false;
+            //consents were given with the old recipient in mind
+            escrow.consents = vec![];
+        }
         escrow.recipient = Some(recipient.clone());
+        escrow.splits = vec![];
+        escrow.dust_recipient = None;
+        escrow.ibc_payout = None;
+        escrow.last_arbiter_action = env.block.height;
         ESCROWS.save(deps.storage, &id, &escrow)?;
 
         Ok(Response::new().add_attributes(vec![
@@ -192,767 +562,4657 @@ pub mod execute {
         ]))
     }
 
+    pub fn execute_set_recipients(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        recipients: Vec<RecipientShareMsg>,
+        dust_recipient: Option<String>,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        //the recipients of hash-locked and swap escrows are not up to the arbiter
+        if info.sender != escrow.arbiter || escrow.hashlock.is_some() || escrow.ask.is_some() {
+            return Err(ContractError::Unauthorized {});
+        }
+        if escrow.pending_decision.is_some() {
+            return Err(ContractError::DecisionPending {});
+        }
+
+        let (mut splits, dust_recipient) = build_shares(deps.api, recipients, dust_recipient)?;
+        //recipients that stay on keep their acceptance
+        for split in splits.iter_mut() {
+            split.accepted = escrow.splits.iter().any(|old| old.address == split.address && old.accepted);
+        }
+        escrow.recipient = None;
+        escrow.recipient_accepted = false;
+        escrow.splits = splits;
+        escrow.dust_recipient = Some(dust_recipient);
+        escrow.ibc_payout = None;
+        escrow.last_arbiter_action = env.block.height;
+        //consents were given with the old recipients in mind
+        escrow.consents = vec![];
+        ESCROWS.save(deps.storage, &id, &escrow)?;
+
+        Ok(Response::new().add_attributes(vec![
+            ("action", "set_recipients"),
+            ("id", id.as_str()),
+        ]))
+    }
+
+    fn build_shares(
+        api: &dyn Api,
+        recipients: Vec<RecipientShareMsg>,
+        dust_recipient: Option<String>,
+    ) -> Result<(Vec<RecipientShare>, Addr), ContractError> {
+        let mut shares: Vec<RecipientShare> = vec![];
+        for recipient in recipients {
+            let address = api.addr_validate(&recipient.address)?;
+            if recipient.share == 0
+                || recipient.share > TOTAL_SHARES
+                || shares.iter().any(|s| s.address == address)
+            {
+                return Err(ContractError::InvalidShares {});
+            }
+            shares.push(RecipientShare { address, share: recipient.share, accepted: false });
+        }
+        if shares.iter().map(|s| s.share).sum::<u64>() != TOTAL_SHARES {
+            return Err(ContractError::InvalidShares {});
+        }
+
+        let dust_recipient = match dust_recipient {
+            Some(addr) => api.addr_validate(&addr)?,
+            None => shares[0].address.clone(),
+        };
+        Ok((shares, dust_recipient))
+    }
+
+    pub fn execute_accept_role(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if escrow.splits.is_empty() {
+            let recipient = escrow.recipient.as_ref().ok_or(ContractError::RecipientNotSet {})?;
+            if info.sender != *recipient {
+                return Err(ContractError::Unauthorized {});
+            }
+            escrow.recipient_accepted = true;
+        } else {
+            let split = escrow
+                .splits
+                .iter_mut()
+                .find(|s| s.address == info.sender)
+                .ok_or(ContractError::Unauthorized {})?;
+            split.accepted = true;
+        }
+        ESCROWS.save(deps.storage, &id, &escrow)?;
+
+        Ok(Response::new().add_attributes(vec![
+            ("action", "accept_role"),
+            ("id", id.as_str()),
+            ("recipient", info.sender.as_str()),
+        ]))
+    }
+
     pub fn execute_top_up(
         deps: DepsMut,
         id: String,
         balance: Balance,
+        funder: &Addr,
     )-> Result<Response, ContractError>{
         if balance.is_empty(){
             return Err(ContractError::EmptyBalance {});
         }
 
         let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        //the offer of a swap is fixed, the filler gets what the source deposited
+        if escrow.ask.is_some() {
+            return Err(ContractError::SwapEscrow {});
+        }
         if let Balance::Cw20(token) = &balance {
             //ensure token is on the whitelist
             if !escrow.cw20_whitelist.iter().any(|t|t == &token.address){
                 return Err(ContractError::NotInWhitelist{});
             }
         }
-        escrow.balance.add_tokens(balance);
+        escrow.balance.add_tokens(balance.clone());
+        lock_stake(deps.storage, &mut escrow)?;
         ESCROWS.save(deps.storage, &id, &escrow)?;
+        CONTRIBUTIONS.update(deps.storage, (&id, funder), |existing| -> StdResult<_> {
+            let mut contribution = existing.unwrap_or_default();
+            contribution.add_tokens(balance.clone());
+            Ok(contribution)
+        })?;
+        let mut deposit = GenericBalance::default();
+        deposit.add_tokens(balance);
+        update_stats(deps.storage, |stats| stats.lock(&deposit))?;
 
         let res = Response::new().add_attributes(vec![("action", "top_up"), ("id", id.as_str())]);
         Ok(res)
     }
 
-    pub fn execute_approve(
+    //locks the configured share of the escrow balance in the staking denom from the arbiter's stake.
+    //The bonded stake must cover what is locked across all escrows of the arbiter
+    fn lock_stake(storage: &mut dyn Storage, escrow: &mut Escrow) -> Result<(), ContractError> {
+        let required = match STAKING_CONFIG.may_load(storage)? {
+            Some(config) => {
+                let value: Uint128 =
+                    escrow.balance.native.iter().filter(|c| c.denom == config.denom).map(|c| c.amount).sum();
+                value.multiply_ratio(config.min_stake_bps, TOTAL_SHARES)
+            }
+            None => Uint128::zero(),
+        };
+        let locked = LOCKED_STAKES.may_load(storage, &escrow.arbiter)?.unwrap_or_default();
+        let locked = locked.saturating_sub(escrow.locked_stake) + required;
+        let bonded = STAKES.may_load(storage, &escrow.arbiter)?.unwrap_or_default();
+        if bonded < locked {
+            return Err(ContractError::InsufficientStake {});
+        }
+        LOCKED_STAKES.save(storage, &escrow.arbiter, &locked)?;
+        escrow.locked_stake = required;
+        Ok(())
+    }
+
+    //gives the stake locked for the escrow back to its arbiter
+    fn release_stake(storage: &mut dyn Storage, escrow: &Escrow) -> StdResult<()> {
+        if escrow.locked_stake.is_zero() {
+            return Ok(());
+        }
+        let locked = LOCKED_STAKES.may_load(storage, &escrow.arbiter)?.unwrap_or_default();
+        LOCKED_STAKES.save(storage, &escrow.arbiter, &locked.saturating_sub(escrow.locked_stake))
+    }
+
+    pub fn execute_stake(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+        let config = STAKING_CONFIG.may_load(deps.storage)?.ok_or(ContractError::StakingDisabled {})?;
+        if !ARBITERS.has(deps.storage, &info.sender) {
+            return Err(ContractError::ArbiterNotRegistered {});
+        }
+        let amount = match info.funds.as_slice() {
+            [coin] if coin.denom == config.denom && !coin.amount.is_zero() => coin.amount,
+            _ => return Err(ContractError::InvalidStake { denom: config.denom }),
+        };
+        let bonded = STAKES.update(deps.storage, &info.sender, |stake| -> StdResult<_> {
+            Ok(stake.unwrap_or_default() + amount)
+        })?;
+        let total = TOTAL_STAKED.may_load(deps.storage)?.unwrap_or_default();
+        TOTAL_STAKED.save(deps.storage, &(total + amount))?;
+
+        Ok(Response::new()
+            .add_attribute("action", "stake")
+            .add_attribute("arbiter", info.sender)
+            .add_attribute("bonded", bonded))
+    }
+
+    pub fn execute_unbond(
         deps: DepsMut,
-        id: String,
         env: Env,
         info: MessageInfo,
-    ) -> Result<Response,ContractError> {
-        let escrow = ESCROWS.load(deps.storage, &id)?;
-        if info.sender != escrow.arbiter {
-            return Err(ContractError::Unauthorized {});
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let config = STAKING_CONFIG.may_load(deps.storage)?.ok_or(ContractError::StakingDisabled {})?;
+        let bonded = STAKES.may_load(deps.storage, &info.sender)?.unwrap_or_default();
+        let bonded = bonded.checked_sub(amount).map_err(StdError::from)?;
+        //stake locked by open escrows stays bonded
+        if bonded < LOCKED_STAKES.may_load(deps.storage, &info.sender)?.unwrap_or_default() {
+            return Err(ContractError::StakeLocked {});
         }
-        if escrow.is_expired(&env){
-            return Err(ContractError::Expired{});
+        STAKES.save(deps.storage, &info.sender, &bonded)?;
+
+        let release_at = config.unbonding_period.after(&env.block);
+        let mut unbonding = UNBONDING.may_load(deps.storage, &info.sender)?.unwrap_or_default();
+        unbonding.push(Unbonding { amount, release_at });
+        UNBONDING.save(deps.storage, &info.sender, &unbonding)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "unbond")
+            .add_attribute("arbiter", info.sender)
+            .add_attribute("amount", amount)
+            .add_attribute("release_at", release_at.to_string()))
+    }
+
+    pub fn execute_claim_unbonded(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+        let config = STAKING_CONFIG.may_load(deps.storage)?.ok_or(ContractError::StakingDisabled {})?;
+        let unbonding = UNBONDING.may_load(deps.storage, &info.sender)?.unwrap_or_default();
+        let (released, pending): (Vec<_>, Vec<_>) =
+            unbonding.into_iter().partition(|u| u.release_at.is_expired(&env.block));
+        let amount: Uint128 = released.iter().map(|u| u.amount).sum();
+        if amount.is_zero() {
+            return Err(ContractError::NothingToClaim {});
         }
+        UNBONDING.save(deps.storage, &info.sender, &pending)?;
+        let total = TOTAL_STAKED.may_load(deps.storage)?.unwrap_or_default();
+        TOTAL_STAKED.save(deps.storage, &total.saturating_sub(amount))?;
 
-        let recipient = escrow.recipient.ok_or(ContractError::RecipientNotSet{})?;
-        
-        //delete the escrow
-        ESCROWS.remove(deps.storage, &id);
+        Ok(Response::new()
+            .add_attribute("action", "claim_unbonded")
+            .add_attribute("arbiter", info.sender.clone())
+            .add_attribute("amount", amount)
+            .add_message(BankMsg::Send { to_address: info.sender.into(), amount: coins(amount.u128(), config.denom) }))
+    }
 
-        //send all tokens out
-        let messages: Vec<SubMsg> = send_tokens(&recipient, &escrow.balance)?;
+    pub fn execute_slash(
+        deps: DepsMut,
+        info: MessageInfo,
+        arbiter: String,
+        id: String,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let config = STAKING_CONFIG.may_load(deps.storage)?.ok_or(ContractError::StakingDisabled {})?;
+        let state = STATE.load(deps.storage)?;
+        if info.sender != state.owner && Some(&info.sender) != config.governance.as_ref() {
+            return Err(ContractError::Unauthorized {});
+        }
+        let arbiter = deps.api.addr_validate(&arbiter)?;
+
+        //only an upheld appeal makes stake slashable, up to what the escrow locked
+        let slashable = SLASHABLE.may_load(deps.storage, (&arbiter, &id))?.ok_or(ContractError::NotSlashable {})?;
+        let amount = std::cmp::min(amount, slashable.amount);
+
+        //bonded stake goes first, then unbonding stake, the most recent first
+        let mut bonded = STAKES.may_load(deps.storage, &arbiter)?.unwrap_or_default();
+        let mut slashed = std::cmp::min(bonded, amount);
+        bonded -= slashed;
+        let mut unbonding = UNBONDING.may_load(deps.storage, &arbiter)?.unwrap_or_default();
+        for entry in unbonding.iter_mut().rev() {
+            let take = std::cmp::min(entry.amount, amount - slashed);
+            entry.amount -= take;
+            slashed += take;
+        }
+        unbonding.retain(|u| !u.amount.is_zero());
+        if slashed.is_zero() {
+            return Err(ContractError::EmptyBalance {});
+        }
+        STAKES.save(deps.storage, &arbiter, &bonded)?;
+        UNBONDING.save(deps.storage, &arbiter, &unbonding)?;
+        let total = TOTAL_STAKED.may_load(deps.storage)?.unwrap_or_default();
+        TOTAL_STAKED.save(deps.storage, &total.saturating_sub(slashed))?;
+        if slashable.amount == slashed {
+            SLASHABLE.remove(deps.storage, (&arbiter, &id));
+        } else {
+            let rest = Slashable { amount: slashable.amount - slashed, appellant: slashable.appellant.clone() };
+            SLASHABLE.save(deps.storage, (&arbiter, &id), &rest)?;
+        }
 
         Ok(Response::new()
-        .add_attribute("action", "approve")
-        .add_attribute("id", id)
-        .add_attribute("to", recipient)
-        .add_submessages(messages))
+            .add_attribute("action", "slash")
+            .add_attribute("arbiter", arbiter)
+            .add_attribute("id", id)
+            .add_attribute("amount", slashed)
+            .add_attribute("to", slashable.appellant.clone())
+            .add_message(BankMsg::Send { to_address: slashable.appellant.into(), amount: coins(slashed.u128(), config.denom) }))
     }
 
-    pub fn execute_receive(
+    pub fn execute_set_staking_config(
         deps: DepsMut,
         info: MessageInfo,
-        wrapper: Cw20ReceiveMsg,
+        denom: String,
+        min_stake_bps: u64,
+        unbonding_period: Duration,
+        governance: Option<String>,
     ) -> Result<Response, ContractError> {
-        let msg: ReceiveMsg = from_binary(&wrapper.msg)?;
-        let balance = Balance:: Cw20(Cw20CoinVerified { address: info.sender, amount: wrapper.amount });
-        let api = deps.api;
-        match msg {
-            ReceiveMsg:: Create(msg) => {
-                execute_create(deps, msg, balance, &api.addr_validate(&wrapper.sender)?)
+        let state = STATE.load(deps.storage)?;
+        if info.sender != state.owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        //stakes are kept as amounts of the current denom
+        if let Some(current) = STAKING_CONFIG.may_load(deps.storage)? {
+            if current.denom != denom && !TOTAL_STAKED.may_load(deps.storage)?.unwrap_or_default().is_zero() {
+                return Err(ContractError::StakesOutstanding {});
             }
-            ReceiveMsg::TopUp { id } => execute_top_up(deps, id, balance),
         }
+        let governance = governance.map(|addr| deps.api.addr_validate(&addr)).transpose()?;
+        let config = StakingConfig { denom, min_stake_bps, unbonding_period, governance };
+        STAKING_CONFIG.save(deps.storage, &config)?;
+
+        Ok(Response::new().add_attribute("action", "set_staking_config"))
     }
 
-    pub fn execute_refund(
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute_create_from_template(
         deps: DepsMut,
         env: Env,
-        info: MessageInfo,
+        template: String,
         id: String,
+        version: Option<u64>,
+        overrides: TemplateOverrides,
+        balance: Balance,
+        sender: &Addr,
     ) -> Result<Response, ContractError> {
-        let escrow = ESCROWS.load(deps.storage, &id)?;
-        if !escrow.is_expired(&env) || info.sender != escrow.arbiter {
-            Err(ContractError::Unauthorized {})
-        } else {
-            //delete the escrow
-            ESCROWS.remove(deps.storage, &id);
-
-            //send all tokens out
-            let messages = send_tokens(&escrow.source, &escrow.balance)?;
-            Ok(Response::new()
-                .add_attribute("action", "refund")
-                .add_attribute("id", id)
-                .add_attribute("to", escrow.source)
-                .add_submessages(messages))
+        let stored = TEMPLATES.load(deps.storage, &template)?;
+        //the owner may have changed the template since the creator looked at it
+        if version.is_some_and(|version| version != stored.version) {
+            return Err(ContractError::TemplateChanged { version: stored.version });
+        }
+        let mut msg: CreateMsg = from_binary(&stored.defaults)?;
+        msg.id = id;
+        if overrides.recipient.is_some() || overrides.recipients.is_some() {
+            msg.recipient = overrides.recipient;
+            msg.recipients = overrides.recipients;
+        }
+        if overrides.expires.is_some() || overrides.expires_in.is_some() {
+            msg.expires = overrides.expires;
+            msg.expires_in = overrides.expires_in;
+        }
+        if let Some(title) = overrides.title {
+            msg.title = title;
+        }
+        if let Some(description) = overrides.description {
+            msg.description = description;
         }
-    }
-}
 
-fn send_tokens(to: &Addr, balance: &GenericBalance) -> StdResult<Vec<SubMsg>> {
-    let native_balance = &balance.native;
-    let mut msgs: Vec<SubMsg> = if native_balance.is_empty(){
-        vec![]
-    } else {
-        vec![SubMsg:: new(BankMsg:: Send { to_address: to.into(), amount: native_balance.to_vec() })]
-    };
+        //the result is validated like any other escrow
+        let res = execute_create(deps, env, msg, balance, sender)?;
+        Ok(res.add_attribute("template", template).add_attribute("template_version", stored.version.to_string()))
+    }
 
-    let cw20_balance = &balance.cw20;
-    let cw20_msgs: StdResult<Vec<_>> = cw20_balance
-        .iter()
-        .map(|c|{
-            let msg = Cw20ExecuteMsg:: Transfer { recipient: to.into(), amount: c.amount };
-            let exec = SubMsg::new(WasmMsg::Execute { contract_addr: c.address.to_string(), msg: to_binary(&msg)?, funds: vec![] });
-            Ok(exec)
-        })
-        .collect();
-    msgs.append(&mut cw20_msgs?);
-    Ok(msgs)
-}
+    pub fn execute_register_template(
+        deps: DepsMut,
+        info: MessageInfo,
+        name: String,
+        defaults: CreateMsg,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        let (owner, version) = match TEMPLATES.may_load(deps.storage, &name)? {
+            Some(existing) if info.sender != existing.owner && info.sender != state.owner => {
+                return Err(ContractError::Unauthorized {})
+            }
+            Some(existing) => (existing.owner, existing.version + 1),
+            None => (info.sender, 1),
+        };
+        //catch bad addresses now rather than on every escrow
+        deps.api.addr_validate(&defaults.arbiter)?;
+        defaults.addr_whitelist(deps.api)?;
+        let template = Template { owner, version, defaults: to_binary(&defaults)? };
+        TEMPLATES.save(deps.storage, &name, &template)?;
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetCount {} => to_binary(&query::count(deps)?),
-        QueryMsg:: List {} => to_binary(&query_list(deps)?),
-        QueryMsg:: Details { id } => to_binary(&query_detail(deps, id)?),
+        Ok(Response::new()
+            .add_attribute("action", "register_template")
+            .add_attribute("name", name)
+            .add_attribute("version", version.to_string()))
     }
-}
-
-pub mod query {
-    use super::*;
 
-    pub fn count(deps: Deps) -> StdResult<GetCountResponse> {
+    pub fn execute_remove_template(
+        deps: DepsMut,
+        info: MessageInfo,
+        name: String,
+    ) -> Result<Response, ContractError> {
         let state = STATE.load(deps.storage)?;
-        Ok(GetCountResponse { count: state.count })
+        let template = TEMPLATES.load(deps.storage, &name)?;
+        if info.sender != template.owner && info.sender != state.owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        TEMPLATES.remove(deps.storage, &name);
+
+        Ok(Response::new()
+            .add_attribute("action", "remove_template")
+            .add_attribute("name", name))
     }
 
-    pub fn query_list(deps: Deps) -> StdResult<ListResponse>{
-        Ok(ListResponse{
-            escrows: all_escrow_ids(deps.storage)?,
-        })
+    //handlers check everything before they write, so a failed action leaves no changes behind
+    pub fn execute_batch(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        actions: Vec<EscrowAction>,
+        mode: BatchMode,
+    ) -> Result<Response, ContractError> {
+        let mut unallocated = GenericBalance { native: info.funds.clone(), cw20: vec![] };
+        let action_info = MessageInfo { sender: info.sender.clone(), funds: vec![] };
+        let mut res = Response::new().add_attribute("action", "batch");
+        let mut messages: Vec<SubMsg> = vec![];
+        let mut results: Vec<BatchResult> = vec![];
+
+        for (index, action) in actions.into_iter().enumerate() {
+            //each item writes into its own cache over the contract storage, which is only committed when the
+            //item succeeds, so a failed item leaves nothing behind even if it wrote before failing
+            let mut cache = StorageCache::new(deps.storage);
+            let item_deps = DepsMut { storage: &mut cache, api: deps.api, querier: deps.querier };
+            let (id, result) = match action {
+                EscrowAction::Approve { id } => {
+                    (id.clone(), execute_approve(item_deps, id, env.clone(), action_info.clone()))
+                }
+                EscrowAction::Refund { id } => {
+                    (id.clone(), execute_refund(item_deps, env.clone(), action_info.clone(), id))
+                }
+                EscrowAction::SetRecipient { id, recipient } => {
+                    let result = execute_set_recipient(item_deps, env.clone(), action_info.clone(), id.clone(), recipient);
+                    (id, result)
+                }
+                EscrowAction::TopUp { id, amount } => {
+                    let want = GenericBalance { native: amount, cw20: vec![] };
+                    let result = if unallocated.clone().take(&want).matches(&want) {
+                        let result = execute_top_up(item_deps, id.clone(), Balance::from(want.native.clone()), &info.sender);
+                        if result.is_ok() {
+                            unallocated.take(&want);
+                        }
+                        result
+                    } else {
+                        Err(ContractError::TopUpNotCovered {})
+                    };
+                    (id, result)
+                }
+            };
+            match result {
+                Ok(action_res) => {
+                    cache.commit();
+                    messages.extend(action_res.messages);
+                    res = res.add_attributes(action_res.attributes).add_events(action_res.events);
+                    results.push(BatchResult { id, error: None });
+                }
+                Err(err) if mode == BatchMode::AllOrNothing => {
+                    return Err(ContractError::BatchFailed { index: index as u32, error: err.to_string() });
+                }
+                Err(err) => results.push(BatchResult { id, error: Some(err.to_string()) }),
+            }
+        }
+
+        if !unallocated.is_empty() {
+            messages.extend(send_tokens(&info.sender, &unallocated)?);
+        }
+        let failed = results.iter().filter(|r| r.error.is_some()).count();
+        Ok(res
+            .add_attribute("failed", failed.to_string())
+            .add_submessages(merge_payouts(messages)?)
+            .set_data(to_binary(&BatchResponse { results })?))
+    }
+
+    pub fn execute_fund_over_ibc(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        refund_to: IbcPayout,
+        action: IbcFundingMsg,
+    ) -> Result<Response, ContractError> {
+        if refund_to.channel_id.is_empty() || refund_to.receiver.is_empty() {
+            return Err(ContractError::InvalidIbcPayout {});
+        }
+        //the sender is the intermediate account of the middleware, nobody can spend from it
+        REMOTE_FUNDERS.save(deps.storage, &info.sender, &refund_to)?;
+        let balance = Balance::from(info.funds);
+        let res = match action {
+            IbcFundingMsg::Create(msg) => execute_create(deps, env, *msg, balance, &info.sender)?,
+            IbcFundingMsg::TopUp { id } => execute_top_up(deps, id, balance, &info.sender)?,
+        };
+        Ok(res.add_attribute("refund_to", format!("{}/{}", refund_to.channel_id, refund_to.receiver)))
+    }
+
+    pub fn execute_claim_refund(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let mut returned = RETURNED_REFUNDS
+            .may_load(deps.storage, (&id, &info.sender))?
+            .ok_or(ContractError::NothingToClaim {})?;
+        RETURNED_REFUNDS.remove(deps.storage, (&id, &info.sender));
+        returned.add_tokens(Balance::from(info.funds));
+        //tracked like the first attempt, what fails again can be claimed again
+        let messages = send_payouts(deps.storage, &env, &id, &[(info.sender.clone(), returned)])?;
+
+        Ok(Response::new()
+            .add_attribute("action", "claim_refund")
+            .add_attribute("id", id)
+            .add_attribute("to", info.sender)
+            .add_submessages(messages))
     }
 
-    pub fn query_detail(deps: Deps, id: String) -> StdResult<DetailsResponse> {
+    pub fn execute_cancel(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
         let escrow = ESCROWS.load(deps.storage, &id)?;
-        let cw20_whitelist = escrow.human_whitelist();
-        let native_balance = escrow.balance.native;
-        let cw20_balance: StdResult<Vec<_>> = escrow
-            .balance
-            .cw20
-            .into_iter()
-            .map(|token|{
-                Ok(Cw20Coin{
-                    address: token.address.into(),
-                    amount: token.amount
-                })
-            })
-            .collect();
+        if info.sender != escrow.source {
+            return Err(ContractError::Unauthorized {});
+        }
+        if escrow.recipient.is_some() || !escrow.splits.is_empty() || escrow.ibc_payout.is_some() {
+            return Err(ContractError::RecipientAlreadySet {});
+        }
+        if escrow.pending_decision.is_some() {
+            return Err(ContractError::DecisionPending {});
+        }
+        if escrow.lock_in_until.is_some_and(|until| !until.is_expired(&env.block)) {
+            return Err(ContractError::LockedIn {});
+        }
+        //hunters that submitted in time are owed a decision by the arbiter
+        if OPEN_SUBMISSIONS.may_load(deps.storage, &id)?.unwrap_or_default() > 0 {
+            return Err(ContractError::OpenSubmissions {});
+        }
 
-        let recipient = escrow.recipient.map(|addr| addr.into_string());
+        //other funders get their part back as with a refund
+        let res = refund_escrow(deps.storage, &env, &info.sender, id, escrow)?;
+        Ok(res.add_attribute("cancelled_by", info.sender))
+    }
+
+    pub fn execute_approve(
+        deps: DepsMut,
+        id: String,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response,ContractError> {
+        let escrow = ESCROWS.load(deps.storage, &id)?;
+        if info.sender != escrow.arbiter {
+            return Err(ContractError::Unauthorized {});
+        }
+        arbiter_approve(deps.storage, &env, id, escrow)
+    }
+
+    pub fn execute_approve_with_signature(
+        deps: DepsMut,
+        env: Env,
+        id: String,
+        signature: Binary,
+        pubkey: Binary,
+        nonce: u64,
+    ) -> Result<Response, ContractError> {
+        let escrow = ESCROWS.load(deps.storage, &id)?;
+        let expected = NONCES.may_load(deps.storage, &escrow.arbiter)?.unwrap_or_default();
+        if nonce != expected {
+            return Err(ContractError::InvalidNonce { expected });
+        }
+
+        let approval = SignedApproval {
+            contract_address: env.contract.address.to_string(),
+            chain_id: env.block.chain_id.clone(),
+            id: id.clone(),
+            action: "approve".to_string(),
+            nonce,
+        };
+        let message = to_binary(&approval)?;
+        let (verified, address) = match pubkey.len() {
+            33 => {
+                let hash = Sha256::digest(message.as_slice());
+                let verified = deps.api.secp256k1_verify(&hash, &signature, &pubkey);
+                (verified, Ripemd160::digest(Sha256::digest(pubkey.as_slice())).to_vec())
+            }
+            32 => {
+                let verified = deps.api.ed25519_verify(&message, &signature, &pubkey);
+                (verified, Sha256::digest(pubkey.as_slice())[..20].to_vec())
+            }
+            _ => return Err(ContractError::InvalidPubkey {}),
+        };
+        if !verified.unwrap_or(false) {
+            return Err(ContractError::InvalidSignature {});
+        }
+        //the key must belong to the arbiter, whatever the address prefix of the chain
+        let arbiter_bytes = bech32::decode(escrow.arbiter.as_str())
+            .ok()
+            .and_then(|(_, data, _)| Vec::<u8>::from_base32(&data).ok());
+        if arbiter_bytes != Some(address) {
+            return Err(ContractError::Unauthorized {});
+        }
+        NONCES.save(deps.storage, &escrow.arbiter, &(nonce + 1))?;
+
+        let res = arbiter_approve(deps.storage, &env, id, escrow)?;
+        Ok(res.add_attribute("nonce", nonce.to_string()))
+    }
+
+    pub fn execute_resolve(
+        deps: DepsMut,
+        env: Env,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let escrow = ESCROWS.load(deps.storage, &id)?;
+        if !escrow.resolver {
+            return Err(ContractError::NoResolver {});
+        }
+        let query = ResolverQueryMsg::Resolution { id: id.clone() };
+        let res: ResolutionResponse = deps.querier.query_wasm_smart(&escrow.arbiter, &query)?;
+        let res = match res.resolution {
+            Resolution::Approve => arbiter_approve(deps.storage, &env, id, escrow)?,
+            Resolution::Refund => {
+                if escrow.pending_decision.is_some() {
+                    return Err(ContractError::DecisionPending {});
+                }
+                arbiter_refund(deps.storage, &env, id, escrow)?
+            }
+            Resolution::Pending => return Err(ContractError::ResolutionPending {}),
+        };
+        Ok(res.add_attribute("resolved_by", "resolver"))
+    }
+
+    //approval by the arbiter, which does not decide hash-locked or swap escrows
+    fn arbiter_approve(
+        storage: &mut dyn Storage,
+        env: &Env,
+        id: String,
+        escrow: Escrow,
+    ) -> Result<Response, ContractError> {
+        if escrow.hashlock.is_some() {
+            return Err(ContractError::Hashlocked {});
+        }
+        if escrow.ask.is_some() {
+            return Err(ContractError::SwapEscrow {});
+        }
+        if escrow.pending_decision.is_some() {
+            return Err(ContractError::DecisionPending {});
+        }
+        if escrow.challenge_period.is_some() {
+            //fail now rather than when the decision is carried out
+            if escrow.is_expired(env) {
+                return Err(ContractError::Expired {});
+            }
+            if escrow.ibc_payout.is_none() {
+                approval_payouts(&escrow)?;
+            }
+            return propose_decision(storage, env, id, escrow, Decision::Approve);
+        }
+        let (arbiter, created_at) = (escrow.arbiter.clone(), escrow.created_at);
+        let res = approve_escrow(storage, env, &arbiter, id, escrow)?;
+        record_decision(storage, env, &arbiter, created_at, true)?;
+        Ok(res)
+    }
+
+    //refund by the arbiter, callers check the escrow is up to the arbiter
+    fn arbiter_refund(
+        storage: &mut dyn Storage,
+        env: &Env,
+        id: String,
+        escrow: Escrow,
+    ) -> Result<Response, ContractError> {
+        if escrow.challenge_period.is_some() {
+            return propose_decision(storage, env, id, escrow, Decision::Refund);
+        }
+        let (arbiter, created_at) = (escrow.arbiter.clone(), escrow.created_at);
+        let res = refund_escrow(storage, env, &arbiter, id, escrow)?;
+        record_decision(storage, env, &arbiter, created_at, false)?;
+        Ok(res)
+    }
+
+    //records the decision of the arbiter, to be carried out after the challenge period
+    fn propose_decision(
+        storage: &mut dyn Storage,
+        env: &Env,
+        id: String,
+        mut escrow: Escrow,
+        decision: Decision,
+    ) -> Result<Response, ContractError> {
+        escrow.pending_decision = Some(PendingDecision { decision, height: env.block.height, appeal: None });
+        escrow.last_arbiter_action = env.block.height;
+        ESCROWS.save(storage, &id, &escrow)?;
+        //the decision is carried out instead, sweeps don't need to look at the escrow anymore
+        unindex_deadlines(storage, &id, &escrow.expires);
+
+        let decision = match decision {
+            Decision::Approve => "approve",
+            Decision::Refund => "refund",
+        };
+        Ok(Response::new()
+            .add_attribute("action", "decide")
+            .add_attribute("id", id)
+            .add_attribute("decision", decision))
+    }
+
+    pub fn execute_mutual(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        decision: Decision,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if escrow.recipient.is_none() && escrow.splits.is_empty() {
+            return Err(ContractError::RecipientNotSet {});
+        }
+        let parties = escrow.parties();
+        if !parties.contains(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        if escrow.pending_decision.is_some() {
+            return Err(ContractError::DecisionPending {});
+        }
+        escrow.consents.retain(|c| c.party != info.sender);
+        escrow.consents.push(Consent { party: info.sender.clone(), decision });
+
+        let action = match decision {
+            Decision::Approve => "mutual_approve",
+            Decision::Refund => "mutual_refund",
+        };
+        let agreed = parties
+            .iter()
+            .all(|party| escrow.consents.iter().any(|c| c.party == *party && c.decision == decision));
+        if !agreed {
+            ESCROWS.save(deps.storage, &id, &escrow)?;
+            return Ok(Response::new()
+                .add_attribute("action", action)
+                .add_attribute("id", id)
+                .add_attribute("party", info.sender));
+        }
+
+        //everyone agreed, so neither the arbiter nor the deadlines matter
+        let res = carry_out(deps.storage, &env, &info.sender, id, escrow, decision)?;
+        Ok(res.add_attribute("mutual", action))
+    }
+
+    pub fn execute_appeal(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        let is_party = info.sender == escrow.source
+            || escrow.recipient.as_ref() == Some(&info.sender)
+            || escrow.splits.iter().any(|s| s.address == info.sender);
+        if !is_party {
+            return Err(ContractError::Unauthorized {});
+        }
+        let period = escrow.challenge_period.unwrap_or_default();
+        let pending = escrow.pending_decision.as_mut().ok_or(ContractError::NoPendingDecision {})?;
+        if pending.appeal.is_some() {
+            return Err(ContractError::AlreadyAppealed {});
+        }
+        if env.block.height >= pending.height.saturating_add(period) {
+            return Err(ContractError::ChallengePeriodOver {});
+        }
+        let bond: Vec<Coin> = escrow.appeal_bond.iter().cloned().collect();
+        if info.funds != bond {
+            let bond = escrow.appeal_bond.as_ref().map_or("no funds".to_string(), |c| c.to_string());
+            return Err(ContractError::InvalidAppealBond { bond });
+        }
+        pending.appeal = Some(Appeal { appellant: info.sender.clone(), bond, height: env.block.height });
+        ESCROWS.save(deps.storage, &id, &escrow)?;
+
+        let mut stats = ARBITER_STATS.may_load(deps.storage, &escrow.arbiter)?.unwrap_or_default();
+        stats.disputes += 1;
+        ARBITER_STATS.save(deps.storage, &escrow.arbiter, &stats)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "appeal")
+            .add_attribute("id", id)
+            .add_attribute("appellant", info.sender))
+    }
+
+    pub fn execute_decide_appeal(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        approve: bool,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if escrow.appeal_arbiter.as_ref() != Some(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        let pending = escrow.pending_decision.take().ok_or(ContractError::NoPendingDecision {})?;
+        let appeal = pending.appeal.ok_or(ContractError::NotAppealed {})?;
+        let decision = if approve { Decision::Approve } else { Decision::Refund };
+
+        //an upheld appeal gets its bond back, otherwise the bond goes to the arbiter
+        let upheld = decision != pending.decision;
+        //the arbiter that was overruled can be slashed by what it staked on the escrow, in favour of the appellant
+        if upheld && !escrow.locked_stake.is_zero() {
+            let slashable = Slashable { amount: escrow.locked_stake, appellant: appeal.appellant.clone() };
+            SLASHABLE.save(deps.storage, (&escrow.arbiter, &id), &slashable)?;
+        }
+        let bond_to = if upheld { appeal.appellant } else { escrow.arbiter.clone() };
+        let created_at = escrow.created_at;
+        let res = carry_out(deps.storage, &env, &info.sender, id, escrow, decision)?;
+        record_decision(deps.storage, &env, &info.sender, created_at, approve)?;
+
+        let mut res = res
+            .add_attribute("appeal", if upheld { "upheld" } else { "rejected" })
+            .add_attribute("bond_to", bond_to.clone());
+        if !appeal.bond.is_empty() {
+            res = res.add_message(BankMsg::Send { to_address: bond_to.into(), amount: appeal.bond });
+        }
+        Ok(res)
+    }
+
+    pub fn execute_finalize_decision(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        let pending = escrow.pending_decision.take().ok_or(ContractError::NoPendingDecision {})?;
+        let period = escrow.challenge_period.unwrap_or_default();
+        match &pending.appeal {
+            //an appeal left undecided for another challenge period lapses, the decision stands
+            Some(appeal) if env.block.height < appeal.height.saturating_add(period) => {
+                return Err(ContractError::AppealPending {})
+            }
+            None if env.block.height < pending.height.saturating_add(period) => {
+                return Err(ContractError::ChallengePeriodActive {})
+            }
+            _ => {}
+        }
+        let (arbiter, created_at) = (escrow.arbiter.clone(), escrow.created_at);
+        let mut res = carry_out(deps.storage, &env, &info.sender, id, escrow, pending.decision)?;
+        record_decision(deps.storage, &env, &arbiter, created_at, pending.decision == Decision::Approve)?;
+        //the appellant is not to blame for the lapse and gets its bond back
+        if let Some(appeal) = pending.appeal.filter(|a| !a.bond.is_empty()) {
+            res = res
+                .add_attribute("appeal", "lapsed")
+                .add_message(BankMsg::Send { to_address: appeal.appellant.into(), amount: appeal.bond });
+        }
+        Ok(res)
+    }
+
+    //pays out a decision that was made in time, even if the escrow expired since
+    fn carry_out(
+        storage: &mut dyn Storage,
+        env: &Env,
+        actor: &Addr,
+        id: String,
+        escrow: Escrow,
+        decision: Decision,
+    ) -> Result<Response, ContractError> {
+        match decision {
+            Decision::Approve => release_escrow(storage, env, actor, id, escrow),
+            Decision::Refund => refund_escrow(storage, env, actor, id, escrow),
+        }
+    }
+
+    //counts a decision towards the reputation of the arbiter
+    fn record_decision(
+        storage: &mut dyn Storage,
+        env: &Env,
+        arbiter: &Addr,
+        created_at: Option<Timestamp>,
+        approved: bool,
+    ) -> StdResult<()> {
+        let mut stats = ARBITER_STATS.may_load(storage, arbiter)?.unwrap_or_default();
+        let (decisions, speed) = stats.rank();
+        if approved {
+            stats.approvals += 1;
+        } else {
+            stats.refunds += 1;
+        }
+        if let Some(created_at) = created_at {
+            stats.timed_decisions += 1;
+            stats.total_resolution_time += env.block.time.seconds().saturating_sub(created_at.seconds());
+        }
+        if ARBITERS.has(storage, arbiter) {
+            RANKED_ARBITERS.remove(storage, (decisions, speed, arbiter));
+            let (decisions, speed) = stats.rank();
+            RANKED_ARBITERS.save(storage, (decisions, speed, arbiter), &())?;
+        }
+        ARBITER_STATS.save(storage, arbiter, &stats)
+    }
+
+    pub fn execute_register_arbiter(
+        deps: DepsMut,
+        info: MessageInfo,
+        profile: ArbiterProfile,
+    ) -> Result<Response, ContractError> {
+        ARBITERS.save(deps.storage, &info.sender, &profile)?;
+        let stats = ARBITER_STATS.may_load(deps.storage, &info.sender)?.unwrap_or_default();
+        let (decisions, speed) = stats.rank();
+        RANKED_ARBITERS.save(deps.storage, (decisions, speed, &info.sender), &())?;
+        Ok(Response::new()
+            .add_attribute("action", "register_arbiter")
+            .add_attribute("arbiter", info.sender))
+    }
+
+    pub fn execute_unregister_arbiter(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+        if !ARBITERS.has(deps.storage, &info.sender) {
+            return Err(ContractError::ArbiterNotRegistered {});
+        }
+        ARBITERS.remove(deps.storage, &info.sender);
+        let stats = ARBITER_STATS.may_load(deps.storage, &info.sender)?.unwrap_or_default();
+        let (decisions, speed) = stats.rank();
+        RANKED_ARBITERS.remove(deps.storage, (decisions, speed, &info.sender));
+        Ok(Response::new()
+            .add_attribute("action", "unregister_arbiter")
+            .add_attribute("arbiter", info.sender))
+    }
+
+    pub fn execute_set_arbiter_policy(
+        deps: DepsMut,
+        info: MessageInfo,
+        require_registered: bool,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if info.sender != state.owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        REQUIRE_REGISTERED_ARBITER.save(deps.storage, &require_registered)?;
+
+        Ok(Response::new().add_attribute("action", "set_arbiter_policy"))
+    }
+
+    pub fn execute_release(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        preimage: Binary,
+    ) -> Result<Response, ContractError> {
+        let escrow = ESCROWS.load(deps.storage, &id)?;
+        let hashlock = escrow.hashlock.clone().ok_or(ContractError::NotHashlocked {})?;
+        if Sha256::digest(preimage.as_slice()).as_slice() != hashlock.as_slice() {
+            return Err(ContractError::InvalidPreimage {});
+        }
+
+        //the counterparty chain needs the preimage to claim its side of the swap
+        let res = approve_escrow(deps.storage, &env, &info.sender, id, escrow)?;
+        Ok(res.add_attribute("preimage", preimage.to_base64()))
+    }
+
+    //releases the escrow to its recipients, callers check who is allowed to approve
+    fn approve_escrow(
+        storage: &mut dyn Storage,
+        env: &Env,
+        actor: &Addr,
+        id: String,
+        escrow: Escrow,
+    ) -> Result<Response, ContractError> {
+        if escrow.is_expired(env){
+            return Err(ContractError::Expired{});
+        }
+        release_escrow(storage, env, actor, id, escrow)
+    }
+
+    //pays the escrow out to its recipients
+    fn release_escrow(
+        storage: &mut dyn Storage,
+        env: &Env,
+        actor: &Addr,
+        id: String,
+        escrow: Escrow,
+    ) -> Result<Response, ContractError> {
+        if let Some(payout) = &escrow.ibc_payout {
+            let to = format!("{}/{}", payout.channel_id, payout.receiver);
+            let archived = vec![Payout { to: to.clone(), amount: escrow.balance.clone() }];
+            close_escrow(storage, env, actor, &id, &escrow, Decision::Approve, archived)?;
+            let contributions = take_contributions(storage, &id)?;
+            //a payout that fails on the way goes back to the funders
+            let messages = crate::ibc::transfer_out(storage, env, &id, payout, &escrow, &contributions)?;
+            return Ok(Response::new()
+                .add_attribute("action", "approve")
+                .add_attribute("id", id)
+                .add_attribute("to", to)
+                .add_submessages(messages));
+        }
+        let payouts = approval_payouts(&escrow)?;
+
+        //delete the escrow
+        close_escrow(storage, env, actor, &id, &escrow, Decision::Approve, archived_payouts(&payouts))?;
+        take_contributions(storage, &id)?;
+
+        //send all tokens out
+        let messages: Vec<SubMsg> = send_payouts(storage, env, &id, &payouts)?;
+
+        Ok(Response::new()
+        .add_attribute("action", "approve")
+        .add_attribute("id", id)
+        .add_attributes(payouts.iter().map(|(to, _)| ("to", to.as_str())))
+        .add_submessages(messages))
+    }
+
+    //who receives what when the escrow is approved
+    fn approval_payouts(escrow: &Escrow) -> Result<Vec<(Addr, GenericBalance)>, ContractError> {
+        if escrow.splits.is_empty() {
+            let recipient = escrow.recipient.clone().ok_or(ContractError::RecipientNotSet{})?;
+            if escrow.require_acceptance && !escrow.recipient_accepted {
+                return Err(ContractError::RecipientNotAccepted {});
+            }
+            return Ok(vec![(recipient, escrow.balance.clone())]);
+        }
+
+        if escrow.require_acceptance && escrow.splits.iter().any(|s| !s.accepted) {
+            return Err(ContractError::RecipientNotAccepted {});
+        }
+        let dust_recipient = escrow.dust_recipient.as_ref().unwrap_or(&escrow.splits[0].address);
+        Ok(escrow.balance.split(&escrow.splits, dust_recipient))
+    }
+
+    pub fn execute_fill_swap(
+        deps: DepsMut,
+        env: Env,
+        id: String,
+        balance: Balance,
+        filler: &Addr,
+    ) -> Result<Response, ContractError> {
+        let escrow = ESCROWS.load(deps.storage, &id)?;
+        let ask = escrow.ask.as_ref().ok_or(ContractError::NotASwap {})?;
+        if escrow.is_expired(&env) {
+            return Err(ContractError::Expired {});
+        }
+        if escrow.recipient.as_ref().is_some_and(|recipient| recipient != filler) {
+            return Err(ContractError::Unauthorized {});
+        }
+        let mut deposit = GenericBalance::default();
+        deposit.add_tokens(balance);
+        if !deposit.matches(ask) {
+            return Err(ContractError::AskNotMet {});
+        }
+
+        //delete the escrow and settle both sides
+        let payouts = vec![(filler.clone(), escrow.balance.clone()), (escrow.source.clone(), deposit)];
+        close_escrow(deps.storage, &env, filler, &id, &escrow, Decision::Approve, archived_payouts(&payouts))?;
+        take_contributions(deps.storage, &id)?;
+        let messages = send_payouts(deps.storage, &env, &id, &payouts)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "fill_swap")
+            .add_attribute("id", id)
+            .add_attribute("filler", filler)
+            .add_submessages(messages))
+    }
+
+    pub fn execute_receive(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        wrapper: Cw20ReceiveMsg,
+    ) -> Result<Response, ContractError> {
+        let msg: ReceiveMsg = from_binary(&wrapper.msg)?;
+        let balance = Balance:: Cw20(Cw20CoinVerified { address: info.sender, amount: wrapper.amount });
+        let api = deps.api;
+        match msg {
+            ReceiveMsg:: Create(msg) => {
+                execute_create(deps, env, *msg, balance, &api.addr_validate(&wrapper.sender)?)
+            }
+            ReceiveMsg::TopUp { id } => {
+                execute_top_up(deps, id, balance, &api.addr_validate(&wrapper.sender)?)
+            }
+            ReceiveMsg::FillSwap { id } => {
+                execute_fill_swap(deps, env, id, balance, &api.addr_validate(&wrapper.sender)?)
+            }
+            ReceiveMsg::CreateFromTemplate { template, id, version, overrides } => {
+                let sender = api.addr_validate(&wrapper.sender)?;
+                execute_create_from_template(deps, env, template, id, version, overrides, balance, &sender)
+            }
+        }
+    }
+
+    pub fn execute_refund(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let escrow = ESCROWS.load(deps.storage, &id)?;
+        //refunds wait for the escrow to expire, then anyone can refund the source. A refund by the
+        //arbiter counts as its decision, except on hash-locked and swap escrows
+        if !escrow.is_expired(&env) {
+            return Err(ContractError::Unauthorized {});
+        }
+        let by_arbiter = info.sender == escrow.arbiter && escrow.hashlock.is_none() && escrow.ask.is_none();
+        if escrow.pending_decision.is_some() {
+            return Err(ContractError::DecisionPending {});
+        }
+        if by_arbiter {
+            return arbiter_refund(deps.storage, &env, id, escrow);
+        }
+        refund_escrow(deps.storage, &env, &info.sender, id, escrow)
+    }
+
+    //an approved payout that did not arrive on the other chain goes to the funders instead
+    pub fn refund_ibc_transfer(
+        storage: &mut dyn Storage,
+        env: &Env,
+        transfer: IbcTransfer,
+    ) -> Result<Response, ContractError> {
+        let to = format!("{}/{}", transfer.payout.channel_id, transfer.payout.receiver);
+        if let Some(mut settlement) = SETTLED.may_load(storage, &transfer.id)? {
+            let returned = GenericBalance { native: vec![transfer.amount.clone()], cw20: vec![] };
+            for payout in settlement.payouts.iter_mut().filter(|p| p.to == to) {
+                payout.amount.take(&returned);
+            }
+            settlement.payouts.retain(|p| !p.amount.is_empty());
+            settlement.payouts.extend(archived_payouts(&transfer.refunds));
+            for (funder, _) in &transfer.refunds {
+                if !settlement.parties.contains(funder) {
+                    SETTLED_BY_PARTY.save(storage, (funder, &transfer.id), &())?;
+                    settlement.parties.push(funder.clone());
+                }
+            }
+            //nothing arrived, so the escrow was refunded after all
+            if settlement.outcome == Decision::Approve && !settlement.payouts.iter().any(|p| p.to == to) {
+                settlement.outcome = Decision::Refund;
+                update_stats(storage, |stats| {
+                    stats.approved = stats.approved.saturating_sub(1);
+                    stats.refunded += 1;
+                })?;
+            }
+            SETTLED.save(storage, &transfer.id, &settlement)?;
+        }
+
+        let messages = send_payouts(storage, env, &transfer.id, &transfer.refunds)?;
+        Ok(Response::new()
+            .add_attribute("action", "refund_payout")
+            .add_attribute("id", transfer.id)
+            .add_attributes(transfer.refunds.iter().map(|(to, _)| ("to", to.as_str())))
+            .add_submessages(messages))
+    }
+
+    //most escrows refunded by one sweep
+    const MAX_SWEEP: u32 = 30;
+
+    pub fn execute_sweep_expired(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        limit: u32,
+    ) -> Result<Response, ContractError> {
+        let expired = take_expired(deps.storage, &env, limit.min(MAX_SWEEP) as usize)?;
+
+        let keeper_tip = KEEPER_TIP.may_load(deps.storage)?;
+        let mut tips = Uint128::zero();
+        let mut messages: Vec<SubMsg> = vec![];
+        let mut swept: Vec<String> = vec![];
+        for (id, escrow) in expired {
+            //no more than the escrow holds of the tip denom, so dust escrows don't drain the reserve,
+            //and nothing for sweeping your own
+            if let Some(tip) = keeper_tip.as_ref().filter(|_| escrow.source != info.sender) {
+                let held = escrow.balance.native.iter().find(|c| c.denom == tip.denom).map(|c| c.amount);
+                tips += tip.amount.min(held.unwrap_or_default());
+            }
+            messages.extend(refund_escrow(deps.storage, &env, &info.sender, id.clone(), escrow)?.messages);
+            swept.push(id);
+        }
+
+        //the tip is capped by what is left in the reserve
+        let tip = match keeper_tip {
+            Some(tip) => {
+                let want = GenericBalance { native: vec![Coin { denom: tip.denom, amount: tips }], cw20: vec![] };
+                let mut reserve = KEEPER_RESERVE.may_load(deps.storage)?.unwrap_or_default();
+                let tip = reserve.take(&want);
+                KEEPER_RESERVE.save(deps.storage, &reserve)?;
+                tip
+            }
+            None => GenericBalance::default(),
+        };
+        messages.extend(send_tokens(&info.sender, &tip)?);
+
+        Ok(Response::new()
+            .add_attribute("action", "sweep_expired")
+            .add_attribute("swept", swept.len().to_string())
+            .add_attribute("keeper", info.sender)
+            .add_submessages(merge_payouts(messages)?)
+            .set_data(to_binary(&SweepResponse { swept })?))
+    }
+
+    pub fn execute_fund_keeper_reserve(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+        let balance = Balance::from(info.funds);
+        if balance.is_empty() {
+            return Err(ContractError::EmptyBalance {});
+        }
+        let mut reserve = KEEPER_RESERVE.may_load(deps.storage)?.unwrap_or_default();
+        reserve.add_tokens(balance);
+        KEEPER_RESERVE.save(deps.storage, &reserve)?;
+
+        Ok(Response::new().add_attribute("action", "fund_keeper_reserve"))
+    }
+
+    pub fn execute_set_keeper_tip(
+        deps: DepsMut,
+        info: MessageInfo,
+        tip: Option<Coin>,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if info.sender != state.owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        match tip {
+            Some(tip) => KEEPER_TIP.save(deps.storage, &tip)?,
+            None => KEEPER_TIP.remove(deps.storage),
+        }
+
+        Ok(Response::new().add_attribute("action", "set_keeper_tip"))
+    }
+
+    //moves a settled escrow to the archive and takes it out of the running totals and the expiry index
+    fn close_escrow(
+        storage: &mut dyn Storage,
+        env: &Env,
+        actor: &Addr,
+        id: &str,
+        escrow: &Escrow,
+        outcome: Decision,
+        payouts: Vec<Payout>,
+    ) -> StdResult<()> {
+        ESCROWS.remove(storage, id);
+        unindex_deadlines(storage, id, &escrow.expires);
+        release_stake(storage, escrow)?;
+        if escrow.bounty {
+            clear_submissions(storage, id)?;
+        }
+        //the amendment history stays with the settlement, a pending amendment can't be accepted anymore
+        PENDING_AMENDMENTS.remove(storage, id);
+        //funders that are not paid (approvals take the contributions after closing) and whoever
+        //else is paid, such as the swap filler
+        let contributors: Vec<Addr> = CONTRIBUTIONS
+            .prefix(id)
+            .keys(storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?;
+        let remote = escrow.ibc_payout.as_ref().map(|payout| format!("{}/{}", payout.channel_id, payout.receiver));
+        let paid = payouts.iter().filter(|p| Some(&p.to) != remote.as_ref()).map(|p| Addr::unchecked(&p.to));
+        let mut parties = escrow.parties();
+        for party in std::iter::once(escrow.arbiter.clone()).chain(contributors).chain(paid) {
+            if !parties.contains(&party) {
+                parties.push(party);
+            }
+        }
+        for party in &parties {
+            SETTLED_BY_PARTY.save(storage, (party, id), &())?;
+        }
+        let settlement = Settlement {
+            outcome,
+            parties,
+            payouts,
+            height: env.block.height,
+            time: env.block.time,
+            actor: actor.clone(),
+        };
+        SETTLED.save(storage, id, &settlement)?;
+        update_stats(storage, |stats| {
+            stats.open = stats.open.saturating_sub(1);
+            match outcome {
+                Decision::Approve => stats.approved += 1,
+                Decision::Refund => stats.refunded += 1,
+            }
+            stats.unlock(&escrow.balance);
+        })
+    }
+
+    fn update_stats(storage: &mut dyn Storage, update: impl FnOnce(&mut Stats)) -> StdResult<()> {
+        let mut stats = STATS.may_load(storage)?.unwrap_or_default();
+        update(&mut stats);
+        STATS.save(storage, &stats)
+    }
+
+    //returns the escrow to its funders, callers check who is allowed to refund
+    fn refund_escrow(
+        storage: &mut dyn Storage,
+        env: &Env,
+        actor: &Addr,
+        id: String,
+        escrow: Escrow,
+    ) -> Result<Response, ContractError> {
+        //delete the escrow and send all tokens back to the funders
+        let payouts = refund_payouts(storage, &id, &escrow)?;
+        close_escrow(storage, env, actor, &id, &escrow, Decision::Refund, archived_payouts(&payouts))?;
+        let messages = send_payouts(storage, env, &id, &payouts)?;
+        Ok(Response::new()
+            .add_attribute("action", "refund")
+            .add_attribute("id", id)
+            .add_attributes(payouts.iter().map(|(to, _)| ("to", to.as_str())))
+            .add_submessages(messages))
+    }
+
+    pub fn execute_disburse(
+        deps: DepsMut,
+        env: Env,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        let (recipient, payout) = disburse_stream(&mut escrow, &env)?;
+        if payout.is_empty() {
+            return Err(ContractError::NothingDue {});
+        }
+        ESCROWS.save(deps.storage, &id, &escrow)?;
+        update_stats(deps.storage, |stats| stats.unlock(&payout))?;
+
+        let messages = send_tokens(&recipient, &payout)?;
+        Ok(Response::new()
+            .add_attribute("action", "disburse")
+            .add_attribute("id", id)
+            .add_attribute("to", recipient)
+            .add_submessages(messages))
+    }
+
+    pub fn execute_cancel_stream(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if info.sender != escrow.arbiter {
+            return Err(ContractError::Unauthorized {});
+        }
+        if escrow.pending_decision.is_some() {
+            return Err(ContractError::DecisionPending {});
+        }
+
+        //release what is already due, giving the rest back is the arbiter's refund
+        let (recipient, payout) = disburse_stream(&mut escrow, &env)?;
+        update_stats(deps.storage, |stats| stats.unlock(&payout))?;
+        let mut res = arbiter_refund(deps.storage, &env, id, escrow)?;
+        res.messages.splice(0..0, send_tokens(&recipient, &payout)?);
+        Ok(res)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute_amend(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        title: Option<String>,
+        description: Option<String>,
+        expires: Option<Vec<Expiration>>,
+        cw20_whitelist: Option<Vec<String>>,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if info.sender != escrow.source && info.sender != escrow.arbiter {
+            return Err(ContractError::Unauthorized {});
+        }
+
+        let mut changes = vec![];
+        if let Some(new) = title.filter(|t| t != &escrow.title) {
+            changes.push(Change::Title { old: escrow.title.clone(), new });
+        }
+        if let Some(new) = description.filter(|d| d != &escrow.description) {
+            changes.push(Change::Description { old: escrow.description.clone(), new });
+        }
+        if let Some(expires) = expires {
+            let new: Vec<Expiration> = expires.into_iter().filter(|e| e != &Expiration::Never {}).collect();
+            if new != escrow.expires {
+                check_deadlines(&env, &new)?;
+                check_duration_limits(deps.storage, &env, &new, escrow.expiry_mode)?;
+                changes.push(Change::Expires { old: escrow.expires.clone(), new });
+            }
+        }
+        if let Some(whitelist) = cw20_whitelist {
+            let new = whitelist
+                .iter()
+                .map(|h| deps.api.addr_validate(h))
+                .collect::<StdResult<Vec<_>>>()?;
+            if new != escrow.cw20_whitelist {
+                changes.push(Change::Cw20Whitelist { old: escrow.cw20_whitelist.clone(), new });
+            }
+        }
+        if changes.is_empty() {
+            return Err(ContractError::NoChanges {});
+        }
+        //the pending proposal has to be accepted or rejected first
+        if PENDING_AMENDMENTS.has(deps.storage, &id) {
+            return Err(ContractError::AmendmentPending {});
+        }
+
+        let mut amendment = Amendment {
+            changes,
+            proposer: info.sender.clone(),
+            accepted_by: None,
+            height: env.block.height,
+        };
+
+        //the source can push back its own deadlines, anything else waits for the other party
+        let unilateral = info.sender == escrow.source
+            && (info.sender == escrow.arbiter || amendment.changes.iter().all(Change::is_extension));
+        if !unilateral {
+            PENDING_AMENDMENTS.save(deps.storage, &id, &amendment)?;
+            return Ok(Response::new()
+                .add_attribute("action", "propose_amendment")
+                .add_attribute("id", id)
+                .add_attribute("proposer", info.sender));
+        }
+
+        if info.sender == escrow.arbiter {
+            amendment.accepted_by = Some(info.sender.clone());
+            escrow.last_arbiter_action = env.block.height;
+        }
+        apply_amendment(deps.storage, &id, &mut escrow, amendment)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "amend")
+            .add_attribute("id", id)
+            .add_attribute("proposer", info.sender))
+    }
+
+    pub fn execute_accept_amendment(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        let mut amendment = PENDING_AMENDMENTS.load(deps.storage, &id)?;
+        let other_party = if amendment.proposer == escrow.arbiter { &escrow.source } else { &escrow.arbiter };
+        if info.sender != *other_party {
+            return Err(ContractError::Unauthorized {});
+        }
+
+        PENDING_AMENDMENTS.remove(deps.storage, &id);
+        if info.sender == escrow.arbiter {
+            escrow.last_arbiter_action = env.block.height;
+        }
+        amendment.accepted_by = Some(info.sender.clone());
+        amendment.height = env.block.height;
+        apply_amendment(deps.storage, &id, &mut escrow, amendment)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "accept_amendment")
+            .add_attribute("id", id)
+            .add_attribute("accepted_by", info.sender))
+    }
+
+    pub fn execute_reject_amendment(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let escrow = ESCROWS.load(deps.storage, &id)?;
+        if info.sender != escrow.source && info.sender != escrow.arbiter {
+            return Err(ContractError::Unauthorized {});
+        }
+        PENDING_AMENDMENTS.load(deps.storage, &id)?;
+        PENDING_AMENDMENTS.remove(deps.storage, &id);
+
+        Ok(Response::new()
+            .add_attribute("action", "reject_amendment")
+            .add_attribute("id", id))
+    }
+
+    //applies all changes and appends the amendment to the escrow history
+    fn apply_amendment(
+        storage: &mut dyn Storage,
+        id: &str,
+        escrow: &mut Escrow,
+        amendment: Amendment,
+    ) -> Result<(), ContractError> {
+        let deadlines = escrow.expires.clone();
+        for change in &amendment.changes {
+            //values must not have moved since the proposal
+            let unchanged = match change {
+                Change::Title { old, new } => {
+                    let unchanged = &escrow.title == old;
+                    escrow.title = new.clone();
+                    unchanged
+                }
+                Change::Description { old, new } => {
+                    let unchanged = &escrow.description == old;
+                    escrow.description = new.clone();
+                    unchanged
+                }
+                Change::Expires { old, new } => {
+                    let unchanged = &escrow.expires == old;
+                    escrow.expires = new.clone();
+                    unchanged
+                }
+                Change::Cw20Whitelist { old, new } => {
+                    let unchanged = &escrow.cw20_whitelist == old;
+                    escrow.cw20_whitelist = new.clone();
+                    unchanged
+                }
+            };
+            if !unchanged {
+                return Err(ContractError::StaleAmendment {});
+            }
+        }
+        ESCROWS.save(storage, id, escrow)?;
+        unindex_deadlines(storage, id, &deadlines);
+        if escrow.pending_decision.is_none() {
+            index_deadlines(storage, id, &escrow.expires)?;
+        }
+
+        let next = AMENDMENTS
+            .prefix(id)
+            .keys(storage, None, None, Order::Descending)
+            .next()
+            .transpose()?
+            .map_or(0, |last| last + 1);
+        AMENDMENTS.save(storage, (id, next), &amendment)?;
+        Ok(())
+    }
+
+    pub fn execute_submit_claim(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        link: String,
+        hunter: Option<String>,
+    ) -> Result<Response, ContractError> {
+        let escrow = load_open_bounty(deps.storage, &env, &id)?;
+        if escrow.sealed {
+            return Err(ContractError::SealedBounty {});
+        }
+        let hunter = match hunter {
+            Some(hunter) => deps.api.addr_validate(&hunter)?,
+            None => info.sender.clone(),
+        };
+
+        let submission_id = next_submission_id(deps.storage, &env, &id, &info.sender)?;
+        let submission = Submission {
+            id: submission_id,
+            hunter: hunter.clone(),
+            link,
+            submitter: info.sender.clone(),
+            height: env.block.height,
+            status: SubmissionStatus::Open,
+            commitment: None,
+            revealed_at: None,
+        };
+        SUBMISSIONS.save(deps.storage, (&id, submission_id), &submission)?;
+        open_submission(deps.storage, &id)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "submit_claim")
+            .add_attribute("id", id)
+            .add_attribute("submission_id", submission_id.to_string())
+            .add_attribute("hunter", hunter))
+    }
+
+    pub fn execute_commit_claim(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        commitment: Binary,
+    ) -> Result<Response, ContractError> {
+        let escrow = load_open_bounty(deps.storage, &env, &id)?;
+        if !escrow.sealed {
+            return Err(ContractError::NotSealed {});
+        }
+        if escrow.reveal_start.is_some_and(|start| start.is_expired(&env.block)) {
+            return Err(ContractError::CommitPhaseOver {});
+        }
+
+        let submission_id = next_submission_id(deps.storage, &env, &id, &info.sender)?;
+        let submission = Submission {
+            id: submission_id,
+            hunter: info.sender.clone(),
+            link: String::new(),
+            submitter: info.sender.clone(),
+            height: env.block.height,
+            status: SubmissionStatus::Committed,
+            commitment: Some(commitment),
+            revealed_at: None,
+        };
+        SUBMISSIONS.save(deps.storage, (&id, submission_id), &submission)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "commit_claim")
+            .add_attribute("id", id)
+            .add_attribute("submission_id", submission_id.to_string()))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute_reveal_claim(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        submission_id: u64,
+        link: String,
+        hunter: Option<String>,
+        salt: String,
+    ) -> Result<Response, ContractError> {
+        let escrow = load_open_bounty(deps.storage, &env, &id)?;
+        let mut submission = SUBMISSIONS.load(deps.storage, (&id, submission_id))?;
+        if info.sender != submission.submitter {
+            return Err(ContractError::Unauthorized {});
+        }
+        if submission.status != SubmissionStatus::Committed {
+            return Err(ContractError::SubmissionNotOpen {});
+        }
+        let started = match escrow.reveal_start {
+            Some(start) => start.is_expired(&env.block),
+            None => env.block.height > submission.height,
+        };
+        if !started {
+            return Err(ContractError::RevealPhaseNotStarted {});
+        }
+
+        let hunter = match hunter {
+            Some(hunter) => deps.api.addr_validate(&hunter)?,
+            None => info.sender.clone(),
+        };
+        let preimage = SealedClaim {
+            id: id.clone(),
+            link,
+            hunter: hunter.to_string(),
+            salt,
+        };
+        let hash = Sha256::digest(to_binary(&preimage)?.as_slice());
+        if submission.commitment.as_ref().map(|c| c.as_slice()) != Some(hash.as_slice()) {
+            return Err(ContractError::InvalidReveal {});
+        }
+
+        submission.hunter = hunter.clone();
+        submission.link = preimage.link;
+        submission.status = SubmissionStatus::Open;
+        submission.revealed_at = Some(env.block.height);
+        SUBMISSIONS.save(deps.storage, (&id, submission_id), &submission)?;
+        open_submission(deps.storage, &id)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "reveal_claim")
+            .add_attribute("id", id)
+            .add_attribute("submission_id", submission_id.to_string())
+            .add_attribute("hunter", hunter))
+    }
+
+    //loads a bounty that still takes submissions
+    fn load_open_bounty(storage: &dyn Storage, env: &Env, id: &str) -> Result<Escrow, ContractError> {
+        let escrow = ESCROWS.load(storage, id)?;
+        if !escrow.bounty {
+            return Err(ContractError::NotABounty {});
+        }
+        if escrow.recipient.is_some() || !escrow.splits.is_empty() || escrow.is_expired(env) {
+            return Err(ContractError::BountyClosed {});
+        }
+        Ok(escrow)
+    }
+
+    //checks the submission limits of the submitter and reserves the next submission id
+    fn next_submission_id(
+        storage: &mut dyn Storage,
+        env: &Env,
+        id: &str,
+        submitter: &Addr,
+    ) -> Result<u64, ContractError> {
+        let limits = SUBMISSION_LIMITS.may_load(storage)?.unwrap_or_default();
+        if let Some(last) = LAST_SUBMISSION.may_load(storage, submitter)? {
+            if env.block.height < last.saturating_add(limits.cooldown_blocks) {
+                return Err(ContractError::RateLimited {});
+            }
+        }
+        let count = SUBMISSION_COUNTS.may_load(storage, (id, submitter))?.unwrap_or_default();
+        if limits.max_per_bounty.is_some_and(|max| count >= max) {
+            return Err(ContractError::TooManySubmissions {});
+        }
+        SUBMISSION_COUNTS.save(storage, (id, submitter), &(count + 1))?;
+        LAST_SUBMISSION.save(storage, submitter, &env.block.height)?;
+
+        let submission_id = SUBMISSIONS
+            .prefix(id)
+            .keys(storage, None, None, Order::Descending)
+            .next()
+            .transpose()?
+            .map_or(0, |last| last + 1);
+        Ok(submission_id)
+    }
+
+    pub fn execute_take_over_arbitration(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if escrow.backup_arbiter.as_ref() != Some(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        if !escrow.arbiter_inactive(&env) {
+            return Err(ContractError::ArbiterActive {});
+        }
+        escrow.backup_arbiter = None;
+        let event = hand_over(deps.storage, &env, &id, &mut escrow, info.sender, "backup")?;
+
+        Ok(Response::new()
+            .add_attribute("action", "take_over_arbitration")
+            .add_attribute("id", id)
+            .add_event(event))
+    }
+
+    pub fn execute_nominate_arbiter(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        arbiter: String,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        let nominee = deps.api.addr_validate(&arbiter)?;
+        if !escrow.arbiter_inactive(&env) {
+            return Err(ContractError::ArbiterActive {});
+        }
+        //any of the split recipients nominates for the recipient side
+        let is_recipient = escrow.recipient.as_ref() == Some(&info.sender)
+            || escrow.splits.iter().any(|s| s.address == info.sender);
+        if info.sender == escrow.source {
+            escrow.source_nominee = Some(nominee.clone());
+        } else if is_recipient {
+            escrow.recipient_nominee = Some(nominee.clone());
+        } else {
+            return Err(ContractError::Unauthorized {});
+        }
+
+        let res = Response::new()
+            .add_attribute("action", "nominate_arbiter")
+            .add_attribute("id", id.clone())
+            .add_attribute("nominee", nominee.clone());
+        if escrow.source_nominee != escrow.recipient_nominee {
+            ESCROWS.save(deps.storage, &id, &escrow)?;
+            return Ok(res);
+        }
+        let event = hand_over(deps.storage, &env, &id, &mut escrow, nominee, "joint_appointment")?;
+        Ok(res.add_event(event))
+    }
+
+    //replaces the arbiter, which starts a new inactivity window
+    fn hand_over(
+        storage: &mut dyn Storage,
+        env: &Env,
+        id: &str,
+        escrow: &mut Escrow,
+        arbiter: Addr,
+        reason: &str,
+    ) -> Result<Event, ContractError> {
+        let event = Event::new("arbiter_handover")
+            .add_attribute("id", id)
+            .add_attribute("from", escrow.arbiter.clone())
+            .add_attribute("to", arbiter.clone())
+            .add_attribute("reason", reason);
+        //the new arbiter meets the same requirements as one named at creation
+        if REQUIRE_REGISTERED_ARBITER.may_load(storage)?.unwrap_or_default() && !ARBITERS.has(storage, &arbiter) {
+            return Err(ContractError::ArbiterNotRegistered {});
+        }
+        //and takes over the stake locked for the escrow
+        release_stake(storage, escrow)?;
+        escrow.locked_stake = Uint128::zero();
+        escrow.arbiter = arbiter;
+        lock_stake(storage, escrow)?;
+        escrow.last_arbiter_action = env.block.height;
+        escrow.source_nominee = None;
+        escrow.recipient_nominee = None;
+        ESCROWS.save(storage, id, escrow)?;
+        //amendments proposed to or by the old arbiter no longer apply
+        PENDING_AMENDMENTS.remove(storage, id);
+        Ok(event)
+    }
+
+    pub fn execute_pick_winner(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        submission_id: u64,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if info.sender != escrow.arbiter {
+            return Err(ContractError::Unauthorized {});
+        }
+        if !escrow.bounty {
+            return Err(ContractError::NotABounty {});
+        }
+        if escrow.pending_decision.is_some() {
+            return Err(ContractError::DecisionPending {});
+        }
+        let mut submission = SUBMISSIONS.load(deps.storage, (&id, submission_id))?;
+        if submission.status != SubmissionStatus::Open {
+            return Err(ContractError::SubmissionNotOpen {});
+        }
+        submission.status = SubmissionStatus::Won;
+        SUBMISSIONS.save(deps.storage, (&id, submission_id), &submission)?;
+        close_submission(deps.storage, &id)?;
+
+        //a hunter that submitted for itself has already taken on the role
+        let accepted = submission.submitter == submission.hunter
+            || (escrow.recipient_accepted && escrow.recipient.as_ref() == Some(&submission.hunter));
+        escrow.recipient_accepted = accepted;
+        escrow.recipient = Some(submission.hunter);
+        escrow.splits = vec![];
+        escrow.dust_recipient = None;
+        //a hunter named by someone else accepts first, the arbiter approves after that
+        if escrow.require_acceptance && !accepted {
+            escrow.last_arbiter_action = env.block.height;
+            ESCROWS.save(deps.storage, &id, &escrow)?;
+            return Ok(Response::new()
+                .add_attribute("action", "pick_winner")
+                .add_attribute("id", id)
+                .add_attribute("submission_id", submission_id.to_string()));
+        }
+
+        //picking the winner is the arbiter's approval, open to appeal like any other
+        let res = arbiter_approve(deps.storage, &env, id, escrow)?;
+        Ok(res.add_attribute("submission_id", submission_id.to_string()))
+    }
+
+    pub fn execute_reject_submission(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: String,
+        submission_id: u64,
+    ) -> Result<Response, ContractError> {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if info.sender != escrow.arbiter {
+            return Err(ContractError::Unauthorized {});
+        }
+        let mut submission = SUBMISSIONS.load(deps.storage, (&id, submission_id))?;
+        if submission.status != SubmissionStatus::Open {
+            return Err(ContractError::SubmissionNotOpen {});
+        }
+        submission.status = SubmissionStatus::Rejected;
+        SUBMISSIONS.save(deps.storage, (&id, submission_id), &submission)?;
+        close_submission(deps.storage, &id)?;
+        escrow.last_arbiter_action = env.block.height;
+        ESCROWS.save(deps.storage, &id, &escrow)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "reject_submission")
+            .add_attribute("id", id)
+            .add_attribute("submission_id", submission_id.to_string()))
+    }
+
+    //an open submission keeps the source from cancelling until the arbiter decides it, an unrevealed
+    //commitment doesn't
+    fn open_submission(storage: &mut dyn Storage, id: &str) -> StdResult<()> {
+        let open = OPEN_SUBMISSIONS.may_load(storage, id)?.unwrap_or_default();
+        OPEN_SUBMISSIONS.save(storage, id, &(open + 1))
+    }
+
+    //a decided submission no longer keeps the source from cancelling
+    fn close_submission(storage: &mut dyn Storage, id: &str) -> StdResult<()> {
+        let open = OPEN_SUBMISSIONS.may_load(storage, id)?.unwrap_or_default();
+        OPEN_SUBMISSIONS.save(storage, id, &open.saturating_sub(1))
+    }
+
+    pub fn execute_set_submission_limits(
+        deps: DepsMut,
+        info: MessageInfo,
+        limits: SubmissionLimits,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if info.sender != state.owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        SUBMISSION_LIMITS.save(deps.storage, &limits)?;
+
+        Ok(Response::new().add_attribute("action", "set_submission_limits"))
+    }
+
+    //submissions only matter while the bounty is open
+    fn clear_submissions(storage: &mut dyn Storage, id: &str) -> StdResult<()> {
+        let submissions: Vec<u64> = SUBMISSIONS
+            .prefix(id)
+            .keys(storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?;
+        for submission_id in submissions {
+            SUBMISSIONS.remove(storage, (id, submission_id));
+        }
+        let submitters: Vec<Addr> = SUBMISSION_COUNTS
+            .prefix(id)
+            .keys(storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?;
+        for submitter in &submitters {
+            SUBMISSION_COUNTS.remove(storage, (id, submitter));
+        }
+        OPEN_SUBMISSIONS.remove(storage, id);
+        Ok(())
+    }
+
+    //loads and removes the deposits of all funders of an escrow
+    fn take_contributions(storage: &mut dyn Storage, id: &str) -> StdResult<Vec<(Addr, GenericBalance)>> {
+        let contributions: Vec<(Addr, GenericBalance)> = CONTRIBUTIONS
+            .prefix(id)
+            .range(storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?;
+        for (funder, _) in &contributions {
+            CONTRIBUTIONS.remove(storage, (id, funder));
+        }
+        Ok(contributions)
+    }
+
+    //what is left in the escrow goes back to the funders pro rata, rounding leftovers to the source
+    fn refund_payouts(
+        storage: &mut dyn Storage,
+        id: &str,
+        escrow: &Escrow,
+    ) -> StdResult<Vec<(Addr, GenericBalance)>> {
+        let contributions = take_contributions(storage, id)?;
+        Ok(escrow.balance.split_pro_rata(&contributions, &escrow.source))
+    }
+
+    fn archived_payouts(payouts: &[(Addr, GenericBalance)]) -> Vec<Payout> {
+        payouts.iter().map(|(to, amount)| Payout { to: to.to_string(), amount: amount.clone() }).collect()
+    }
+
+    //moves the stream forward and takes the due payments out of the escrow balance
+    fn disburse_stream(escrow: &mut Escrow, env: &Env) -> Result<(Addr, GenericBalance), ContractError> {
+        let stream = escrow.stream.as_mut().ok_or(ContractError::NotAStream {})?;
+        let periods = stream.periods_due(env);
+        stream.advance(periods);
+        let due = stream.amount.times(periods)?;
+        Ok((stream.recipient.clone(), escrow.balance.take(&due)))
+    }
+}
+
+fn send_all(payouts: &[(Addr, GenericBalance)]) -> StdResult<Vec<SubMsg>> {
+    let mut msgs: Vec<SubMsg> = vec![];
+    for (to, balance) in payouts {
+        msgs.append(&mut send_tokens(to, balance)?);
+    }
+    Ok(msgs)
+}
+
+//like send_all, but funders that came in over IBC are paid back on their chain
+fn send_payouts(
+    storage: &mut dyn Storage,
+    env: &Env,
+    id: &str,
+    payouts: &[(Addr, GenericBalance)],
+) -> StdResult<Vec<SubMsg>> {
+    let mut msgs: Vec<SubMsg> = vec![];
+    for (to, balance) in payouts {
+        match REMOTE_FUNDERS.may_load(storage, to)? {
+            Some(route) => msgs.extend(crate::ibc::transfer_back(storage, env, id, &route, to, balance)?),
+            None => msgs.extend(send_tokens(to, balance)?),
+        }
+    }
+    Ok(msgs)
+}
+
+//combines plain token transfers into one per recipient and asset, other messages are kept as they are
+fn merge_payouts(messages: Vec<SubMsg>) -> StdResult<Vec<SubMsg>> {
+    let mut payouts: Vec<(Addr, GenericBalance)> = vec![];
+    let mut others: Vec<SubMsg> = vec![];
+    let mut add = |to: String, balance: Balance| match payouts.iter_mut().find(|(addr, _)| addr.as_str() == to) {
+        Some((_, payout)) => payout.add_tokens(balance),
+        None => {
+            let mut payout = GenericBalance::default();
+            payout.add_tokens(balance);
+            payouts.push((Addr::unchecked(to), payout));
+        }
+    };
+    for msg in messages {
+        match &msg.msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) if msg.id == 0 => {
+                add(to_address.clone(), Balance::from(amount.clone()))
+            }
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, msg: inner, funds }) if msg.id == 0 && funds.is_empty() => {
+                match from_binary(inner) {
+                    Ok(Cw20ExecuteMsg::Transfer { recipient, amount }) => {
+                        let token = Cw20CoinVerified { address: Addr::unchecked(contract_addr), amount };
+                        add(recipient, Balance::Cw20(token))
+                    }
+                    _ => others.push(msg),
+                }
+            }
+            _ => others.push(msg),
+        }
+    }
+    let mut merged = send_all(&payouts)?;
+    merged.extend(others);
+    Ok(merged)
+}
+
+pub(crate) fn send_tokens(to: &Addr, balance: &GenericBalance) -> StdResult<Vec<SubMsg>> {
+    let native_balance = &balance.native;
+    let mut msgs: Vec<SubMsg> = if native_balance.is_empty(){
+        vec![]
+    } else {
+        vec![SubMsg:: new(BankMsg:: Send { to_address: to.into(), amount: native_balance.to_vec() })]
+    };
+
+    let cw20_balance = &balance.cw20;
+    let cw20_msgs: StdResult<Vec<_>> = cw20_balance
+        .iter()
+        .map(|c|{
+            let msg = Cw20ExecuteMsg:: Transfer { recipient: to.into(), amount: c.amount };
+            let exec = SubMsg::new(WasmMsg::Execute { contract_addr: c.address.to_string(), msg: to_binary(&msg)?, funds: vec![] });
+            Ok(exec)
+        })
+        .collect();
+    msgs.append(&mut cw20_msgs?);
+    Ok(msgs)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetCount {} => to_binary(&query::count(deps)?),
+        QueryMsg:: List {} => to_binary(&query_list(deps)?),
+        QueryMsg:: Details { id } => to_binary(&query_detail(deps, env, id)?),
+        QueryMsg:: Amendments { id } => to_binary(&query_amendments(deps, id)?),
+        QueryMsg:: Contributors { id } => to_binary(&query_contributors(deps, id)?),
+        QueryMsg:: Submissions { id, start_after, limit } => to_binary(&query_submissions(deps, id, start_after, limit)?),
+        QueryMsg:: SubmissionLimits {} => to_binary(&SUBMISSION_LIMITS.may_load(deps.storage)?.unwrap_or_default()),
+        QueryMsg:: Arbiter { address } => to_binary(&query_arbiter(deps, address)?),
+        QueryMsg:: Arbiters { start_after, limit } => to_binary(&query_arbiters(deps, start_after, limit)?),
+        QueryMsg:: RankedArbiters { limit } => to_binary(&query_ranked_arbiters(deps, limit)?),
+        QueryMsg:: Stake { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            to_binary(&StakeResponse {
+                bonded: STAKES.may_load(deps.storage, &address)?.unwrap_or_default(),
+                unbonding: UNBONDING.may_load(deps.storage, &address)?.unwrap_or_default(),
+            })
+        }
+        QueryMsg:: StakingConfig {} => to_binary(&STAKING_CONFIG.load(deps.storage)?),
+        QueryMsg:: Template { name } => to_binary(&query_template(deps, name)?),
+        QueryMsg:: Templates { start_after, limit } => to_binary(&query_templates(deps, start_after, limit)?),
+        QueryMsg:: Nonce { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            to_binary(&NonceResponse { nonce: NONCES.may_load(deps.storage, &address)?.unwrap_or_default() })
+        }
+        QueryMsg:: Stats {} => to_binary(&query_stats(deps, env)?),
+        QueryMsg:: SettledDetails { id } => to_binary(&query_settled(deps, id)?),
+        QueryMsg:: SettledHistory { party, start_after, limit } => {
+            to_binary(&query_settled_history(deps, party, start_after, limit)?)
+        }
+        QueryMsg:: Keeper {} => {
+            let tip = KEEPER_TIP.may_load(deps.storage)?;
+            let reserve = KEEPER_RESERVE.may_load(deps.storage)?.unwrap_or_default().native;
+            to_binary(&KeeperResponse { tip, reserve: reserve.into_iter().filter(|c| !c.amount.is_zero()).collect() })
+        }
+        QueryMsg:: DurationLimits {} => to_binary(&DURATION_LIMITS.may_load(deps.storage)?.unwrap_or_default()),
+    }
+}
+
+pub mod query {
+    use super::*;
+
+    const DEFAULT_LIMIT: u32 = 10;
+    const MAX_LIMIT: u32 = 30;
+
+    pub fn count(deps: Deps) -> StdResult<GetCountResponse> {
+        let state = STATE.load(deps.storage)?;
+        Ok(GetCountResponse { count: state.count })
+    }
+
+    pub fn query_list(deps: Deps) -> StdResult<ListResponse>{
+        Ok(ListResponse{
+            escrows: all_escrow_ids(deps.storage)?,
+        })
+    }
+
+    pub fn query_detail(deps: Deps, env: Env, id: String) -> StdResult<DetailsResponse> {
+        let escrow = ESCROWS.load(deps.storage, &id)?;
+        let expires_in = escrow.expires_in(&env);
+        let cw20_whitelist = escrow.human_whitelist();
+        let native_balance = escrow.balance.native;
+        let cw20_balance: StdResult<Vec<_>> = escrow
+            .balance
+            .cw20
+            .into_iter()
+            .map(|token|{
+                Ok(Cw20Coin{
+                    address: token.address.into(),
+                    amount: token.amount
+                })
+            })
+            .collect();
+
+        let recipient = escrow.recipient.map(|addr| addr.into_string());
+        let stream = escrow.stream.map(|stream| StreamDetails {
+            native: stream.amount.native,
+            cw20: stream
+                .amount
+                .cw20
+                .into_iter()
+                .map(|token| Cw20Coin { address: token.address.into(), amount: token.amount })
+                .collect(),
+            period: stream.period,
+            recipient: stream.recipient.into(),
+            last_disbursed: stream.last_disbursed,
+        });
+
+        let detail = DetailsResponse{
+            id,
+            arbiter: escrow.arbiter.into(),
+            recipient,
+            title: escrow.title,
+            description: escrow.description,
+            expires: escrow.expires,
+            expiry_mode: escrow.expiry_mode,
+            expires_in,
+            cw20_balance: cw20_balance?,
+            source: escrow.source.into(),
+            native_balance: native_balance,
+            cw20_whitelist,
+            stream,
+            require_acceptance: escrow.require_acceptance,
+            recipient_accepted: escrow.recipient_accepted,
+            recipients: escrow.splits,
+            dust_recipient: escrow.dust_recipient.map(|addr| addr.into_string()),
+            bounty: escrow.bounty,
+            sealed: escrow.sealed,
+            reveal_start: escrow.reveal_start,
+            hashlock: escrow.hashlock,
+            ask: escrow.ask.map(|ask| SwapAsk {
+                native: ask.native,
+                cw20: ask
+                    .cw20
+                    .into_iter()
+                    .map(|token| Cw20Coin { address: token.address.into(), amount: token.amount })
+                    .collect(),
+            }),
+            resolver: escrow.resolver,
+            last_arbiter_action: escrow.last_arbiter_action,
+            inactivity_window: escrow.inactivity_window,
+            backup_arbiter: escrow.backup_arbiter.map(|addr| addr.into_string()),
+            challenge_period: escrow.challenge_period,
+            appeal_arbiter: escrow.appeal_arbiter.map(|addr| addr.into_string()),
+            appeal_bond: escrow.appeal_bond,
+            pending_decision: escrow.pending_decision,
+            consents: escrow.consents,
+            lock_in_until: escrow.lock_in_until,
+            ibc_payout: escrow.ibc_payout,
+        };
+        Ok(detail)
+    }
+
+    pub fn query_arbiter(deps: Deps, address: String) -> StdResult<ArbiterResponse> {
+        let address = deps.api.addr_validate(&address)?;
+        let profile = ARBITERS.may_load(deps.storage, &address)?;
+        arbiter_response(deps, address, profile)
+    }
+
+    pub fn query_arbiters(
+        deps: Deps,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> StdResult<ArbitersResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start_after = start_after.map(|addr| deps.api.addr_validate(&addr)).transpose()?;
+        let arbiters: StdResult<Vec<_>> = ARBITERS
+            .range(deps.storage, start_after.as_ref().map(Bound::exclusive), None, Order::Ascending)
+            .take(limit)
+            .map(|item| {
+                let (address, profile) = item?;
+                arbiter_response(deps, address, Some(profile))
+            })
+            .collect();
+        Ok(ArbitersResponse { arbiters: arbiters? })
+    }
+
+    pub fn query_ranked_arbiters(deps: Deps, limit: Option<u32>) -> StdResult<ArbitersResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let arbiters = RANKED_ARBITERS
+            .keys(deps.storage, None, None, Order::Descending)
+            .take(limit)
+            .map(|item| {
+                let (_, _, address) = item?;
+                let profile = ARBITERS.may_load(deps.storage, &address)?;
+                arbiter_response(deps, address, profile)
+            })
+            .collect::<StdResult<Vec<_>>>()?;
+        Ok(ArbitersResponse { arbiters })
+    }
+
+    fn arbiter_response(deps: Deps, address: Addr, profile: Option<ArbiterProfile>) -> StdResult<ArbiterResponse> {
+        let stats = ARBITER_STATS.may_load(deps.storage, &address)?.unwrap_or_default();
+        Ok(ArbiterResponse {
+            address: address.into_string(),
+            profile,
+            average_resolution_time: stats.average_resolution_time(),
+            stats,
+        })
+    }
+
+    //the counters are kept by the handlers, nothing else is looked up
+    pub fn query_stats(deps: Deps, env: Env) -> StdResult<StatsResponse> {
+        let stats = STATS.may_load(deps.storage)?.unwrap_or_default();
+        Ok(StatsResponse {
+            open: stats.open,
+            expired_unclaimed: count_expired(deps.storage, &env)?,
+            created: stats.created,
+            approved: stats.approved,
+            refunded: stats.refunded,
+            locked_native: stats.locked.native.into_iter().filter(|c| !c.amount.is_zero()).collect(),
+            locked_cw20: stats
+                .locked
+                .cw20
+                .into_iter()
+                .filter(|c| !c.amount.is_zero())
+                .map(|c| Cw20Coin { address: c.address.into_string(), amount: c.amount })
+                .collect(),
+            donated: stats.donated.native,
+        })
+    }
+
+    pub fn query_settled(deps: Deps, id: String) -> StdResult<SettledResponse> {
+        let settlement = SETTLED.load(deps.storage, &id)?;
+        Ok(SettledResponse { id, settlement })
+    }
+
+    pub fn query_settled_history(
+        deps: Deps,
+        party: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> StdResult<SettledHistoryResponse> {
+        let party = deps.api.addr_validate(&party)?;
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.as_deref().map(Bound::exclusive);
+        let escrows: StdResult<Vec<_>> = SETTLED_BY_PARTY
+            .prefix(&party)
+            .keys(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .map(|id| query_settled(deps, id?))
+            .collect();
+        Ok(SettledHistoryResponse { escrows: escrows? })
+    }
+
+    pub fn query_template(deps: Deps, name: String) -> StdResult<TemplateResponse> {
+        let template = TEMPLATES.load(deps.storage, &name)?;
+        template_response(name, template)
+    }
+
+    fn template_response(name: String, template: Template) -> StdResult<TemplateResponse> {
+        Ok(TemplateResponse {
+            name,
+            owner: template.owner.into_string(),
+            version: template.version,
+            defaults: from_binary(&template.defaults)?,
+        })
+    }
+
+    pub fn query_templates(
+        deps: Deps,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> StdResult<TemplatesResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let templates: StdResult<Vec<_>> = TEMPLATES
+            .range(deps.storage, start_after.as_deref().map(Bound::exclusive), None, Order::Ascending)
+            .take(limit)
+            .map(|item| {
+                let (name, template) = item?;
+                template_response(name, template)
+            })
+            .collect();
+        Ok(TemplatesResponse { templates: templates? })
+    }
+
+    pub fn query_submissions(
+        deps: Deps,
+        id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    ) -> StdResult<SubmissionsResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let submissions: StdResult<Vec<_>> = SUBMISSIONS
+            .prefix(&id)
+            .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(_, submission)| submission))
+            .collect();
+        Ok(SubmissionsResponse { submissions: submissions? })
+    }
+
+    pub fn query_contributors(deps: Deps, id: String) -> StdResult<ContributorsResponse> {
+        ESCROWS.load(deps.storage, &id)?;
+        let contributors: StdResult<Vec<_>> = CONTRIBUTIONS
+            .prefix(&id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| {
+                let (address, deposit) = item?;
+                Ok(Contribution {
+                    address: address.into_string(),
+                    native: deposit.native,
+                    cw20: deposit
+                        .cw20
+                        .into_iter()
+                        .map(|token| Cw20Coin { address: token.address.into(), amount: token.amount })
+                        .collect(),
+                })
+            })
+            .collect();
+        Ok(ContributorsResponse { contributors: contributors? })
+    }
+
+    pub fn query_amendments(deps: Deps, id: String) -> StdResult<AmendmentsResponse> {
+        //settled escrows keep their history
+        if !SETTLED.has(deps.storage, &id) {
+            ESCROWS.load(deps.storage, &id)?;
+        }
+        let history: StdResult<Vec<_>> = AMENDMENTS
+            .prefix(&id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, amendment)| amendment))
+            .collect();
+        Ok(AmendmentsResponse {
+            pending: PENDING_AMENDMENTS.may_load(deps.storage, &id)?,
+            history: history?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, Addr, Empty, attr, CosmosMsg, Uint128,StdError, Event};
+    use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+    use crate::msg::ExecuteMsg::TopUp;
+    use crate::state::{ArbiterStats, Consent, Decision, ExpiryMode, LegacyEscrow, PendingDecision, EXPIRING_AT_HEIGHT};
+    fn counting_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(execute, instantiate, query);
+        Box::new(contract)
+    }
+
+    #[test]
+    fn proper_initialization() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg { count: 17 , minimal_donation: coin(10, "atom")};
+        let info = mock_info("creator", &coins(1000, "atom"));
+
+        // we can just call .unwrap() to assert this was a success
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // it worked, let's query the state
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
+        let value: GetCountResponse = from_binary(&res).unwrap();
+        assert_eq!(17, value.count);
+    }
+
+    #[test]
+    fn increment() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg { count: 17 , minimal_donation: coin(10, "atom")};
+        let info = mock_info("creator", &coins(2, "atom"));
+        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // beneficiary can release it
+        let info = mock_info("anyone", &coins(2, "atom"));
+        let msg = ExecuteMsg::Increment {};
+        let _res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // should increase counter by 1
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
+        let value: GetCountResponse = from_binary(&res).unwrap();
+        assert_eq!(18, value.count);
+    }
+
+    #[test]
+    fn reset() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg { count: 17, minimal_donation: coin(10, "atom") };
+        let info = mock_info("creator", &coins(2, "atom"));
+        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // beneficiary can release it
+        let unauth_info = mock_info("anyone", &coins(2, "atom"));
+        let msg = ExecuteMsg::Reset { count: 5 };
+        let res = execute(deps.as_mut(), mock_env(), unauth_info, msg);
+        match res {
+            Err(ContractError::Unauthorized {}) => {}
+            _ => panic!("Must return unauthorized error"),
+        }
+
+        // only the original creator can reset the counter
+        let auth_info = mock_info("creator", &coins(2, "atom"));
+        let msg = ExecuteMsg::Reset { count: 5 };
+        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg).unwrap();
+
+        // should now be 5
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
+        let value: GetCountResponse = from_binary(&res).unwrap();
+        assert_eq!(5, value.count);
+    }
+
+    #[test]
+    fn donate(){
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg { count: 17, minimal_donation: coin(10, "atom") };
+        let info = mock_info("creator", &coins(2, "atom"));
+        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let auth_info = mock_info("creator", &coins(2, "atom"));
+        let msg = ExecuteMsg:: Donate {};
+        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg);
+
+        // should increase counter by 1
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
+        let value: GetCountResponse = from_binary(&res).unwrap();
+        assert_eq!(18, value.count);
+    }
+
+    #[test]
+    fn withdraw(){
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg { count: 17, minimal_donation: coin(10, "atom") };
+        let info = mock_info("owner", &coins(100, "atom"));
+        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+            
+        let auth_info = mock_info("sender", &coins(2, "atom"));
+        let msg = ExecuteMsg:: Donate {};
+        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg);
+
+        let auth_info = mock_info("owner", &coins(2, "atom"));
+        let msg = ExecuteMsg:: Withdraw {};
+        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg);
+
+        // assert_eq!(deps.as_mut().querier.query_all_balances("owner").unwrap(),
+        //             coins(10, "atom"));
+        assert_eq!(deps.as_mut().querier.query_all_balances("sender").unwrap(),
+                    vec![]);
+        assert_eq!(deps.as_mut().querier.query_all_balances(mock_env().contract.address).unwrap(),
+                    vec![]);
+
+    }
+
+    #[test]
+    fn withdraw2(){
+        let owner = Addr::unchecked("owner");
+        let sender = Addr::unchecked("sender");
+
+        let mut app = App::new(|router, _api, storage| {
+            router
+                .bank
+                .init_balance(storage, &sender, coins(10, "atom"))
+                .unwrap();
+        });
+
+        let contract_id = app.store_code(counting_contract());
+
+        let contract_addr = app
+            .instantiate_contract(
+                contract_id,
+                owner.clone(),
+                &InstantiateMsg {
+                    count: 17, minimal_donation: coin(10, "atom")
+                },
+                &[],
+                "Counting contract",
+                None,
+            )
+            .unwrap();
+
+        app.execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Donate {},
+            &coins(10, "atom"),
+        )
+        .unwrap();
+
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw {},
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(
+            app.wrap().query_all_balances(owner).unwrap(),
+            coins(10, "atom")
+        );
+        assert_eq!(app.wrap().query_all_balances(sender).unwrap(), vec![]);
+        assert_eq!(
+            app.wrap().query_all_balances(contract_addr).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn set_recipient_after_creation() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {count: 17, minimal_donation: coin(10, "atom")};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // create an escrow
+        let create: CreateMsg = CreateMsg {
+            id: "foobar".to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: None,
+            title: "some_title".to_string(),
+            expires: Some(vec![Expiration::AtHeight(123456)]),
+            cw20_whitelist: None,
+            description: "some_description".to_string(),
+            ..Default::default()
+        };
+        let sender = String::from("source");
+        let balance = coins(100, "tokens");
+        let info = mock_info(&sender, &balance);
+        let msg = ExecuteMsg::Create(create.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create"), res.attributes[0]);
+
+        // ensure the details is what we expect
+        let details = query_detail(deps.as_ref(), mock_env(), "foobar".to_string()).unwrap();
+        assert_eq!(
+            details,
+            DetailsResponse {
+                id: "foobar".to_string(),
+                arbiter: String::from("arbitrate"),
+                recipient: None,
+                source: String::from("source"),
+                title: "some_title".to_string(),
+                description: "some_description".to_string(),
+                expires: vec![Expiration::AtHeight(123456)],
+                expiry_mode: ExpiryMode::Any,
+                expires_in: vec![Duration::Height(123456 - mock_env().block.height)],
+                native_balance: balance.clone(),
+                cw20_balance: vec![],
+                cw20_whitelist: vec![],
+                stream: None,
+                require_acceptance: false,
+                recipient_accepted: false,
+                recipients: vec![],
+                dust_recipient: None,
+                bounty: false,
+                sealed: false,
+                reveal_start: None,
+                hashlock: None,
+                ask: None,
+                resolver: false,
+                last_arbiter_action: mock_env().block.height,
+                inactivity_window: None,
+                backup_arbiter: None,
+                challenge_period: None,
+                appeal_arbiter: None,
+                appeal_bond: None,
+                pending_decision: None,
+                consents: vec![],
+                lock_in_until: None,
+                ibc_payout: None,
+            }
+        );
+
+        // approve it, should fail as we have not set recipient
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id });
+        match res {
+            Err(ContractError::RecipientNotSet {}) => {}
+            _ => panic!("Expect recipient not set error"),
+        }
+
+        // test setting recipient not arbiter
+        let msg = ExecuteMsg::SetRecipient {
+            id: create.id.clone(),
+            recipient: "recp".to_string(),
+        };
+        let info = mock_info("someoneelse", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg);
+        match res {
+            Err(ContractError::Unauthorized {}) => {}
+            _ => panic!("Expect unauthorized error"),
+        }
+
+        // test setting recipient valid
+        let msg = ExecuteMsg::SetRecipient {
+            id: create.id.clone(),
+            recipient: "recp".to_string(),
+        };
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 0);
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "set_recipient"),
+                attr("id", create.id.as_str()),
+                attr("recipient", "recp")
+            ]
+        );
+
+        // approve it, should now work with recp
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(("action", "approve"), res.attributes[0]);
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "recp".to_string(),
+                amount: balance,
+            }))
+        );
+    }
+
+    #[test]
+    fn add_tokens_proper() {
+        let mut tokens = GenericBalance::default();
+        tokens.add_tokens(Balance::from(vec![coin(123, "atom"), coin(789, "eth")]));
+        tokens.add_tokens(Balance::from(vec![coin(456, "atom"), coin(12, "btc")]));
+        assert_eq!(
+            tokens.native,
+            vec![coin(579, "atom"), coin(789, "eth"), coin(12, "btc")]
+        );
+    }
+
+    #[test]
+    fn add_cw_tokens_proper() {
+        let mut tokens = GenericBalance::default();
+        let bar_token = Addr::unchecked("bar_token");
+        let foo_token = Addr::unchecked("foo_token");
+        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
+            address: foo_token.clone(),
+            amount: Uint128::new(12345),
+        }));
+        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
+            address: bar_token.clone(),
+            amount: Uint128::new(777),
+        }));
+        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
+            address: foo_token.clone(),
+            amount: Uint128::new(23400),
+        }));
+        assert_eq!(
+            tokens.cw20,
+            vec![
+                Cw20CoinVerified {
+                    address: foo_token,
+                    amount: Uint128::new(35745),
+                },
+                Cw20CoinVerified {
+                    address: bar_token,
+                    amount: Uint128::new(777),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn top_up_mixed_tokens() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {count: 0, minimal_donation: coin(0, "atom")};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // only accept these tokens
+        let whitelist = vec![String::from("bar_token"), String::from("foo_token")];
+
+        // create an escrow with 2 native tokens
+        let create = CreateMsg {
+            id: "foobar".to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(String::from("recd")),
+            title: "some_title".to_string(),
+            cw20_whitelist: Some(whitelist),
+            description: "some_description".to_string(),
+            ..Default::default()
+        };
+        let sender = String::from("source");
+        let balance = vec![coin(100, "fee"), coin(200, "stake")];
+        let info = mock_info(&sender, &balance);
+        let msg = ExecuteMsg::Create(create.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create"), res.attributes[0]);
+
+        // top it up with 2 more native tokens
+        let extra_native = vec![coin(250, "random"), coin(300, "stake")];
+        let info = mock_info(&sender, &extra_native);
+        let top_up = ExecuteMsg::TopUp {
+            id: create.id.clone(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "top_up"), res.attributes[0]);
+
+        // top up with one foreign token
+        let bar_token = String::from("bar_token");
+        let base = TopUp {
+            id: create.id.clone(),
+        };
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("random"),
+            amount: Uint128::new(7890),
+            msg: to_binary(&base).unwrap(),
+        });
+        let info = mock_info(&bar_token, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "top_up"), res.attributes[0]);
+
+        // top with a foreign token not on the whitelist
+        // top up with one foreign token
+        let baz_token = String::from("baz_token");
+        let base = TopUp {
+            id: create.id.clone(),
+        };
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("random"),
+            amount: Uint128::new(7890),
+            msg: to_binary(&base).unwrap(),
+        });
+        let info = mock_info(&baz_token, &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, top_up).unwrap_err();
+        assert_eq!(err, ContractError::NotInWhitelist{});
+
+        // top up with second foreign token
+        let foo_token = String::from("foo_token");
+        let base = TopUp {
+            id: create.id.clone(),
+        };
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("random"),
+            amount: Uint128::new(888),
+            msg: to_binary(&base).unwrap(),
+        });
+        let info = mock_info(&foo_token, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "top_up"), res.attributes[0]);
+
+        // approve it
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
+        assert_eq!(("action", "approve"), res.attributes[0]);
+        assert_eq!(3, res.messages.len());
+
+        // first message releases all native coins
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: create.recipient.clone().unwrap(),
+                amount: vec![coin(100, "fee"), coin(500, "stake"), coin(250, "random")],
+            }))
+        );
+
+        // second one release bar cw20 token
+        let send_msg = Cw20ExecuteMsg::Transfer {
+            recipient: create.recipient.clone().unwrap(),
+            amount: Uint128::new(7890),
+        };
+        assert_eq!(
+            res.messages[1],
+            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: bar_token,
+                msg: to_binary(&send_msg).unwrap(),
+                funds: vec![]
+            }))
+        );
+
+        // third one release foo cw20 token
+        let send_msg = Cw20ExecuteMsg::Transfer {
+            recipient: create.recipient.unwrap(),
+            amount: Uint128::new(888),
+        };
+        assert_eq!(
+            res.messages[2],
+            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: foo_token,
+                msg: to_binary(&send_msg).unwrap(),
+                funds: vec![]
+            }))
+        );
+    }
+
+    #[test]
+    pub fn happy_path_native(){
+        let mut deps = mock_dependencies();
+
+        let instantiate_msg = InstantiateMsg{ count: 0, minimal_donation: coin(0, "atom")};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        let create = CreateMsg{
+            id: "foobar".to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(String::from("recd")),
+            title: "some title".to_string(),
+            description: "some description".to_string(),
+            expires: Some(vec![Expiration::AtHeight(123456)]),
+            cw20_whitelist: None,
+            ..Default::default()
+        };
+        let sender = String:: from("source");
+        let balance = coins(100, "otms");
+        let info = mock_info(&sender, &balance);
+        let msg = ExecuteMsg::Create(create.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create"), res.attributes[0]);
+
+        let details = query_detail(deps.as_ref(), mock_env(), "foobar".to_string()).unwrap();
+        assert_eq!(
+            details,
+            DetailsResponse {
+                id: "foobar".to_string(),
+                arbiter: String::from("arbitrate"),
+                recipient: Some(String::from("recd")),
+                source: String::from("source"),
+                title: "some title".to_string(),
+                description: "some description".to_string(),
+                expires: vec![Expiration::AtHeight(123456)],
+                expiry_mode: ExpiryMode::Any,
+                expires_in: vec![Duration::Height(123456 - mock_env().block.height)],
+                native_balance: balance.clone(),
+                cw20_balance: vec![],
+                cw20_whitelist: vec![],
+                stream: None,
+                require_acceptance: false,
+                recipient_accepted: false,
+                recipients: vec![],
+                dust_recipient: None,
+                bounty: false,
+                sealed: false,
+                reveal_start: None,
+                hashlock: None,
+                ask: None,
+                resolver: false,
+                last_arbiter_action: mock_env().block.height,
+                inactivity_window: None,
+                backup_arbiter: None,
+                challenge_period: None,
+                appeal_arbiter: None,
+                appeal_bond: None,
+                pending_decision: None,
+                consents: vec![],
+                lock_in_until: None,
+                ibc_payout: None,
+            }
+        );
+
+        //approve it
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res=  execute(deps.as_mut(),mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(("action", "approve"), res.attributes[0]);
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send { 
+                to_address: create.recipient.unwrap(),
+                amount: balance,
+            }))
+        );
+
+        //second attempt fails (not found)
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound{..})))
+
+    }
+
+    #[test]
+    pub fn happy_path_cw20(){
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg{count: 0, minimal_donation:coin(0, "atom")};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let rest = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, rest.messages.len());
+
+        //create escrow
+        let create = CreateMsg{
+            id: "foobar".to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(String::from("recd")),
+            title: "Some Title".to_string(),
+            description: "some description".to_string(),
+            cw20_whitelist: Some(vec![String::from("other-token")]),
+            ..Default::default()
+        };
+        let receive = Cw20ReceiveMsg{
+            sender: String::from("source"),
+            amount: Uint128::new(100),
+            msg: to_binary(&ExecuteMsg::Create(create.clone())).unwrap(),
+        };
+        let token_contract = String::from("my-cw20-token");
+        let info = mock_info(&token_contract, &[]);
+        let msg = ExecuteMsg::Receive(receive.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create"), res.attributes[0]);
+
+        //ensure the whitelist is what we expect
+        let details = query_detail(deps.as_ref(), mock_env(), "foobar".to_string()).unwrap();
+        assert_eq!(
+            details,
+            DetailsResponse {
+                id: "foobar".to_string(),
+                arbiter: String::from("arbitrate"),
+                recipient: Some(String::from("recd")),
+                source: String::from("source"),
+                title: "Some Title".to_string(),
+                description: "some description".to_string(),
+                expires: vec![],
+                expiry_mode: ExpiryMode::Any,
+                expires_in: vec![],
+                native_balance: vec![],
+                cw20_balance: vec![Cw20Coin {
+                    address: String::from("my-cw20-token"),
+                    amount: Uint128::new(100),
+                }],
+                cw20_whitelist: vec![String::from("other-token"), String::from("my-cw20-token")],
+                stream: None,
+                require_acceptance: false,
+                recipient_accepted: false,
+                recipients: vec![],
+                dust_recipient: None,
+                bounty: false,
+                sealed: false,
+                reveal_start: None,
+                hashlock: None,
+                ask: None,
+                resolver: false,
+                last_arbiter_action: mock_env().block.height,
+                inactivity_window: None,
+                backup_arbiter: None,
+                challenge_period: None,
+                appeal_arbiter: None,
+                appeal_bond: None,
+                pending_decision: None,
+                consents: vec![],
+                lock_in_until: None,
+                ibc_payout: None,
+            }
+        );
+
+        //approve it
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(("action", "approve"), res.attributes[0]);
+        let send_msg = Cw20ExecuteMsg::Transfer { recipient: create.recipient.unwrap(), amount: receive.amount };
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: token_contract,
+                msg: to_binary(&send_msg).unwrap(),
+                funds: vec![]
+            }))
+        );
+
+        // second attempt fails (not found)
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+
+    }
+
+    #[test]
+    fn stream_disburse_and_cancel() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        let info = mock_info("anyone", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
 
-        let detail = DetailsResponse{
-            id,
-            arbiter: escrow.arbiter.into(),
-            recipient,
-            title: escrow.title,
-            description: escrow.description,
-            end_height: escrow.end_height,
-            end_time: escrow.end_time,
-            cw20_balance: cw20_balance?,
-            source: escrow.source.into(),
-            native_balance: native_balance,
-            cw20_whitelist,
+        // pay 30 tokens to the contributor every 10 blocks out of a pot of 100
+        let create = CreateMsg {
+            id: "payroll".to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "monthly pay".to_string(),
+            description: "pays a contributor".to_string(),
+            stream: Some(StreamMsg {
+                native: coins(30, "atom"),
+                cw20: vec![],
+                period: Duration::Height(10),
+                recipient: String::from("contributor"),
+            }),
+            ..Default::default()
         };
-        Ok(detail)
+        let info = mock_info("source", &coins(100, "atom"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Create(create)).unwrap();
+
+        // nothing is due yet
+        let msg = ExecuteMsg::Disburse { id: "payroll".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::NothingDue {});
+
+        // two and a half periods later two payments are released
+        let mut env = mock_env();
+        env.block.height += 25;
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "contributor".to_string(),
+                amount: coins(60, "atom"),
+            })]
+        );
+        let details = query_detail(deps.as_ref(), mock_env(), "payroll".to_string()).unwrap();
+        assert_eq!(details.native_balance, coins(40, "atom"));
+        assert_eq!(details.stream.unwrap().last_disbursed, mock_env().block.height + 20);
+
+        // only the arbiter can cancel the stream
+        let cancel = ExecuteMsg::CancelStream { id: "payroll".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), cancel.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // one more period passed, which is paid before the remainder goes back to the source
+        env.block.height += 10;
+        let res = execute(deps.as_mut(), env, mock_info("arbitrate", &[]), cancel).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send {
+                    to_address: "contributor".to_string(),
+                    amount: coins(30, "atom"),
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: "source".to_string(),
+                    amount: coins(10, "atom"),
+                }),
+            ]
+        );
+        let err = query_detail(deps.as_ref(), mock_env(), "payroll".to_string()).unwrap_err();
+        assert!(matches!(err, StdError::NotFound { .. }));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{coin, coins, from_binary, Addr, Empty, attr, CosmosMsg, Uint128,StdError};
-    use cw_multi_test::{App, Contract, ContractWrapper, Executor};
-    use crate::msg::ExecuteMsg::TopUp;
-    fn counting_contract() -> Box<dyn Contract<Empty>> {
-        let contract = ContractWrapper::new(execute, instantiate, query);
-        Box::new(contract)
+    #[test]
+    fn stream_limited_to_balance() {
+        let mut tokens = GenericBalance::default();
+        tokens.add_tokens(Balance::from(vec![coin(50, "atom"), coin(10, "eth")]));
+        let due = GenericBalance {
+            native: vec![coin(20, "atom"), coin(15, "eth")],
+            cw20: vec![],
+        }
+        .times(2)
+        .unwrap();
+        let taken = tokens.take(&due);
+        assert_eq!(taken.native, vec![coin(40, "atom"), coin(10, "eth")]);
+        assert_eq!(tokens.native, vec![coin(10, "atom")]);
     }
 
     #[test]
-    fn proper_initialization() {
+    fn amend_needs_both_parties() {
         let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("anyone", &[]), instantiate_msg).unwrap();
 
-        let msg = InstantiateMsg { count: 17 , minimal_donation: coin(10, "atom")};
-        let info = mock_info("creator", &coins(1000, "atom"));
+        let create = CreateMsg {
+            id: "foobar".to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "some title".to_string(),
+            description: "some description".to_string(),
+            expires: Some(vec![Expiration::AtHeight(20_000)]),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "atom"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Create(create)).unwrap();
 
-        // we can just call .unwrap() to assert this was a success
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        // the arbiter proposes a new title and an earlier deadline
+        let amend = ExecuteMsg::Amend {
+            id: "foobar".to_string(),
+            title: Some("new title".to_string()),
+            description: None,
+            expires: Some(vec![Expiration::AtHeight(15_000)]),
+            cw20_whitelist: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), amend).unwrap();
+        assert_eq!(("action", "propose_amendment"), res.attributes[0]);
+        let details = query_detail(deps.as_ref(), mock_env(), "foobar".to_string()).unwrap();
+        assert_eq!(details.title, "some title");
 
-        // it worked, let's query the state
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(17, value.count);
+        // the proposer cannot accept its own amendment
+        let accept = ExecuteMsg::AcceptAmendment { id: "foobar".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), accept.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        execute(deps.as_mut(), mock_env(), mock_info("source", &[]), accept).unwrap();
+        let details = query_detail(deps.as_ref(), mock_env(), "foobar".to_string()).unwrap();
+        assert_eq!(details.title, "new title");
+        assert_eq!(details.expires, vec![Expiration::AtHeight(15_000)]);
+
+        // the source extends its own deadline without the arbiter
+        let amend = ExecuteMsg::Amend {
+            id: "foobar".to_string(),
+            title: None,
+            description: None,
+            expires: Some(vec![Expiration::AtHeight(30_000)]),
+            cw20_whitelist: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("source", &[]), amend).unwrap();
+        assert_eq!(("action", "amend"), res.attributes[0]);
+
+        // but cannot bring it forward alone
+        let amend = ExecuteMsg::Amend {
+            id: "foobar".to_string(),
+            title: None,
+            description: None,
+            expires: Some(vec![Expiration::AtHeight(12_346)]),
+            cw20_whitelist: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("source", &[]), amend).unwrap();
+        assert_eq!(("action", "propose_amendment"), res.attributes[0]);
+
+        // deadlines in the past are rejected
+        let amend = ExecuteMsg::Amend {
+            id: "foobar".to_string(),
+            title: None,
+            description: None,
+            expires: Some(vec![Expiration::AtHeight(mock_env().block.height)]),
+            cw20_whitelist: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("source", &[]), amend).unwrap_err();
+        assert_eq!(err, ContractError::DeadlineInPast {});
+
+        let amendments = query_amendments(deps.as_ref(), "foobar".to_string()).unwrap();
+        assert_eq!(
+            amendments.pending.unwrap().changes,
+            vec![Change::Expires {
+                old: vec![Expiration::AtHeight(30_000)],
+                new: vec![Expiration::AtHeight(12_346)],
+            }]
+        );
+        assert_eq!(amendments.history.len(), 2);
+        assert_eq!(
+            amendments.history[0],
+            Amendment {
+                changes: vec![
+                    Change::Title { old: "some title".to_string(), new: "new title".to_string() },
+                    Change::Expires {
+                        old: vec![Expiration::AtHeight(20_000)],
+                        new: vec![Expiration::AtHeight(15_000)],
+                    },
+                ],
+                proposer: Addr::unchecked("arbitrate"),
+                accepted_by: Some(Addr::unchecked("source")),
+                height: mock_env().block.height,
+            }
+        );
+        assert_eq!(amendments.history[1].accepted_by, None);
+
+        // nothing else can be proposed until the pending amendment is settled
+        let amend = ExecuteMsg::Amend {
+            id: "foobar".to_string(),
+            title: Some("other title".to_string()),
+            description: None,
+            expires: None,
+            cw20_whitelist: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), amend).unwrap_err();
+        assert_eq!(err, ContractError::AmendmentPending {});
+
+        // closing the escrow drops the pending amendment, the history is kept
+        let mut env = mock_env();
+        env.block.height = 30_000;
+        let refund = ExecuteMsg::Refund { id: "foobar".to_string() };
+        execute(deps.as_mut(), env, mock_info("arbitrate", &[]), refund).unwrap();
+        let amendments = query_amendments(deps.as_ref(), "foobar".to_string()).unwrap();
+        assert_eq!(amendments.pending, None);
+        assert_eq!(amendments.history.len(), 2);
+        assert!(query_amendments(deps.as_ref(), "unknown".to_string()).is_err());
     }
 
     #[test]
-    fn increment() {
+    fn create_validates_deadlines() {
         let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+        let env = mock_env();
 
-        let msg = InstantiateMsg { count: 17 , minimal_donation: coin(10, "atom")};
-        let info = mock_info("creator", &coins(2, "atom"));
-        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let create = |id: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "some title".to_string(),
+            description: "some description".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "atom"));
 
-        // beneficiary can release it
-        let info = mock_info("anyone", &coins(2, "atom"));
-        let msg = ExecuteMsg::Increment {};
-        let _res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        // an escrow cannot be born expired
+        let msg = CreateMsg { expires: Some(vec![Expiration::AtHeight(env.block.height)]), ..create("past") };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap_err();
+        assert_eq!(err, ContractError::DeadlineInPast {});
+        let msg = CreateMsg { expires: Some(vec![Expiration::AtTime(env.block.time.minus_seconds(1))]), ..create("past") };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap_err();
+        assert_eq!(err, ContractError::DeadlineInPast {});
 
-        // should increase counter by 1
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(18, value.count);
+        // relative deadlines are resolved against the current block
+        let msg = CreateMsg {
+            expires_in: Some(vec![Duration::Height(100), Duration::Time(3600)]),
+            expiry_mode: Some(ExpiryMode::All),
+            ..create("relative")
+        };
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap();
+        let details = query_detail(deps.as_ref(), env.clone(), "relative".to_string()).unwrap();
+        assert_eq!(
+            details.expires,
+            vec![
+                Expiration::AtHeight(env.block.height + 100),
+                Expiration::AtTime(env.block.time.plus_seconds(3600)),
+            ]
+        );
+        assert_eq!(details.expiry_mode, ExpiryMode::All);
+        assert_eq!(details.expires_in, vec![Duration::Height(100), Duration::Time(3600)]);
+
+        let msg = CreateMsg {
+            expires: Some(vec![Expiration::AtHeight(20_000)]),
+            expires_in: Some(vec![Duration::Height(100)]),
+            ..create("both")
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap_err();
+        assert_eq!(err, ContractError::ConflictingDeadlines {});
+
+        // only the owner sets the limits
+        let limits = ExecuteMsg::SetDurationLimits {
+            min_blocks: Some(10),
+            max_blocks: Some(1_000),
+            min_seconds: None,
+            max_seconds: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), limits.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), limits).unwrap();
+
+        let msg = CreateMsg { expires_in: Some(vec![Duration::Height(5)]), ..create("short") };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap_err();
+        assert_eq!(err, ContractError::DurationTooShort {});
+        let msg = CreateMsg { expires_in: Some(vec![Duration::Height(5_000)]), ..create("long") };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap_err();
+        assert_eq!(err, ContractError::DurationTooLong {});
+        // with a maximum in place an escrow needs a deadline
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(create("open"))).unwrap_err();
+        assert_eq!(err, ContractError::DurationTooLong {});
+        // a far time deadline does not get around the block maximum
+        let far = Expiration::AtTime(env.block.time.plus_seconds(1_000_000));
+        let msg = CreateMsg { expires: Some(vec![far]), ..create("far") };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap_err();
+        assert_eq!(err, ContractError::DurationTooLong {});
+        let msg = CreateMsg {
+            expires: Some(vec![far]),
+            expires_in: Some(vec![Duration::Height(500)]),
+            expiry_mode: Some(ExpiryMode::All),
+            ..create("far")
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap_err();
+        assert_eq!(err, ContractError::DurationTooLong {});
+        let msg = CreateMsg { expires_in: Some(vec![Duration::Time(u64::MAX)]), ..create("overflow") };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::Create(msg)).unwrap_err();
+        assert_eq!(err, ContractError::DeadlineOverflow {});
+
+        let msg = CreateMsg { expires_in: Some(vec![Duration::Height(500)]), ..create("fine") };
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::Create(msg)).unwrap();
+
+        // amendments are held to the same limits
+        let amend = ExecuteMsg::Amend {
+            id: "fine".to_string(),
+            title: None,
+            description: None,
+            expires: Some(vec![Expiration::AtHeight(env.block.height + 5_000)]),
+            cw20_whitelist: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), amend).unwrap_err();
+        assert_eq!(err, ContractError::DurationTooLong {});
+        let amend = ExecuteMsg::Amend {
+            id: "fine".to_string(),
+            title: None,
+            description: None,
+            expires: Some(vec![far]),
+            cw20_whitelist: None,
+        };
+        let err = execute(deps.as_mut(), env, mock_info("source", &[]), amend).unwrap_err();
+        assert_eq!(err, ContractError::DurationTooLong {});
     }
 
     #[test]
-    fn reset() {
+    fn migrate_legacy_deadlines() {
         let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
 
-        let msg = InstantiateMsg { count: 17, minimal_donation: coin(10, "atom") };
-        let info = mock_info("creator", &coins(2, "atom"));
-        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let legacy = LegacyEscrow {
+            arbiter: Addr::unchecked("arbitrate"),
+            recipient: None,
+            source: Addr::unchecked("source"),
+            title: "some title".to_string(),
+            description: "some description".to_string(),
+            end_height: Some(12_345),
+            end_time: Some(mock_env().block.time.seconds() + 1),
+            balance: GenericBalance { native: coins(100, "atom"), cw20: vec![] },
+            cw20_whitelist: vec![],
+        };
+        LEGACY_ESCROWS.save(deps.as_mut().storage, "old", &legacy).unwrap();
 
-        // beneficiary can release it
-        let unauth_info = mock_info("anyone", &coins(2, "atom"));
-        let msg = ExecuteMsg::Reset { count: 5 };
-        let res = execute(deps.as_mut(), mock_env(), unauth_info, msg);
-        match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Must return unauthorized error"),
-        }
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(("migrated", "1"), res.attributes[1]);
 
-        // only the original creator can reset the counter
-        let auth_info = mock_info("creator", &coins(2, "atom"));
-        let msg = ExecuteMsg::Reset { count: 5 };
-        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg).unwrap();
+        // legacy deadlines still hold in the current block and pass in the next one
+        let escrow = ESCROWS.load(deps.as_ref().storage, "old").unwrap();
+        assert_eq!(escrow.expires[0], Expiration::AtHeight(12_346));
+        assert_eq!(escrow.expiry_mode, ExpiryMode::Any);
+        assert!(!escrow.is_expired(&mock_env()));
+        let mut env = mock_env();
+        env.block.height += 1;
+        assert!(escrow.is_expired(&env));
 
-        // should now be 5
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(5, value.count);
+        // running it again leaves migrated escrows alone
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(("migrated", "0"), res.attributes[1]);
     }
 
     #[test]
-    fn donate(){
+    fn recipient_acceptance_handshake() {
         let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
 
-        let msg = InstantiateMsg { count: 17, minimal_donation: coin(10, "atom") };
-        let info = mock_info("creator", &coins(2, "atom"));
-        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let create = CreateMsg {
+            id: "foobar".to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(String::from("xx")),
+            title: "some title".to_string(),
+            description: "some description".to_string(),
+            require_acceptance: true,
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "atom"));
 
-        let auth_info = mock_info("creator", &coins(2, "atom"));
-        let msg = ExecuteMsg:: Donate {};
-        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg);
+        // an invalid recipient is an error instead of being dropped
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::Create(create.clone())).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::GenericErr { .. })));
 
-        // should increase counter by 1
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(18, value.count);
+        let create = CreateMsg { recipient: Some(String::from("recd")), ..create };
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Create(create)).unwrap();
+
+        // approval waits for the recipient
+        let approve = ExecuteMsg::Approve { id: "foobar".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), approve.clone()).unwrap_err();
+        assert_eq!(err, ContractError::RecipientNotAccepted {});
+
+        let accept = ExecuteMsg::AcceptRole { id: "foobar".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), accept.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), mock_info("recd", &[]), accept).unwrap();
+
+        // a new recipient has to accept again
+        let msg = ExecuteMsg::SetRecipient { id: "foobar".to_string(), recipient: "other".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), msg).unwrap();
+        let details = query_detail(deps.as_ref(), mock_env(), "foobar".to_string()).unwrap();
+        assert!(details.require_acceptance);
+        assert!(!details.recipient_accepted);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), approve.clone()).unwrap_err();
+        assert_eq!(err, ContractError::RecipientNotAccepted {});
+
+        let accept = ExecuteMsg::AcceptRole { id: "foobar".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("other", &[]), accept).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), approve).unwrap();
+        assert_eq!(("to", "other"), res.attributes[2]);
     }
 
     #[test]
-    fn withdraw(){
+    fn approve_splits_between_recipients() {
         let mut deps = mock_dependencies();
-        let msg = InstantiateMsg { count: 17, minimal_donation: coin(10, "atom") };
-        let info = mock_info("owner", &coins(100, "atom"));
-        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-            
-        let auth_info = mock_info("sender", &coins(2, "atom"));
-        let msg = ExecuteMsg:: Donate {};
-        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg);
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
 
-        let auth_info = mock_info("owner", &coins(2, "atom"));
-        let msg = ExecuteMsg:: Withdraw {};
-        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg);
+        let share = |address: &str, share: u64| RecipientShareMsg { address: address.to_string(), share };
+        let create = CreateMsg {
+            id: "bounty".to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "some title".to_string(),
+            description: "some description".to_string(),
+            recipients: Some(vec![share("alice", 3_333), share("bob", 3_333), share("carol", 3_333)]),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "atom"));
 
-        // assert_eq!(deps.as_mut().querier.query_all_balances("owner").unwrap(),
-        //             coins(10, "atom"));
-        assert_eq!(deps.as_mut().querier.query_all_balances("sender").unwrap(),
-                    vec![]);
-        assert_eq!(deps.as_mut().querier.query_all_balances(mock_env().contract.address).unwrap(),
-                    vec![]);
+        // shares must add up to the whole balance
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::Create(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::InvalidShares {});
+
+        let create = CreateMsg {
+            recipients: Some(vec![share("alice", 3_334), share("bob", 3_333), share("carol", 3_333)]),
+            dust_recipient: Some("carol".to_string()),
+            ..create
+        };
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Create(create)).unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("arbitrate", &[]),
+            ExecuteMsg::Approve { id: "bounty".to_string() },
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "approve"),
+                attr("id", "bounty"),
+                attr("to", "alice"),
+                attr("to", "bob"),
+                attr("to", "carol"),
+            ]
+        );
+        // 33.34 rounds down to 33 for alice, the one token left over goes to carol
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "alice".to_string(), amount: coins(33, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "bob".to_string(), amount: coins(33, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "carol".to_string(), amount: coins(34, "atom") }),
+            ]
+        );
+    }
 
+    #[test]
+    fn split_cw20_with_outside_dust_recipient() {
+        let token = Addr::unchecked("token");
+        let balance = GenericBalance {
+            native: vec![],
+            cw20: vec![Cw20CoinVerified { address: token.clone(), amount: Uint128::new(7) }],
+        };
+        let shares = vec![
+            RecipientShare { address: Addr::unchecked("alice"), share: 5_000, accepted: false },
+            RecipientShare { address: Addr::unchecked("bob"), share: 5_000, accepted: false },
+        ];
+        let parts = balance.split(&shares, &Addr::unchecked("source"));
+        let amounts: Vec<(String, Uint128)> = parts
+            .into_iter()
+            .map(|(addr, part)| (addr.into_string(), part.cw20[0].amount))
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![
+                ("alice".to_string(), Uint128::new(3)),
+                ("bob".to_string(), Uint128::new(3)),
+                ("source".to_string(), Uint128::new(1)),
+            ]
+        );
     }
 
     #[test]
-    fn withdraw2(){
-        let owner = Addr::unchecked("owner");
-        let sender = Addr::unchecked("sender");
+    fn refund_returns_deposits_pro_rata() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
 
-        let mut app = App::new(|router, _api, storage| {
-            router
-                .bank
-                .init_balance(storage, &sender, coins(10, "atom"))
-                .unwrap();
-        });
+        // 60 from the source, paid out 10 every 10 blocks
+        let create = CreateMsg {
+            id: "pot".to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "some title".to_string(),
+            description: "some description".to_string(),
+            cw20_whitelist: Some(vec![String::from("token")]),
+            stream: Some(StreamMsg {
+                native: coins(10, "atom"),
+                cw20: vec![],
+                period: Duration::Height(10),
+                recipient: String::from("contributor"),
+            }),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(60, "atom")), ExecuteMsg::Create(create)).unwrap();
 
-        let contract_id = app.store_code(counting_contract());
+        // a third party adds 40 atom and some cw20 tokens
+        let top_up = ExecuteMsg::TopUp { id: "pot".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("sponsor", &coins(40, "atom")), top_up).unwrap();
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("sponsor"),
+            amount: Uint128::new(500),
+            msg: to_binary(&TopUp { id: "pot".to_string() }).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("token", &[]), top_up).unwrap();
 
-        let contract_addr = app
-            .instantiate_contract(
-                contract_id,
-                owner.clone(),
-                &InstantiateMsg {
-                    count: 17, minimal_donation: coin(10, "atom")
+        let contributors = query_contributors(deps.as_ref(), "pot".to_string()).unwrap().contributors;
+        assert_eq!(
+            contributors,
+            vec![
+                Contribution {
+                    address: "source".to_string(),
+                    native: coins(60, "atom"),
+                    cw20: vec![],
                 },
-                &[],
-                "Counting contract",
-                None,
-            )
-            .unwrap();
+                Contribution {
+                    address: "sponsor".to_string(),
+                    native: coins(40, "atom"),
+                    cw20: vec![Cw20Coin { address: "token".to_string(), amount: Uint128::new(500) }],
+                },
+            ]
+        );
+
+        // after paying out 50 the remaining 50 atom are returned 60:40
+        let mut env = mock_env();
+        env.block.height += 50;
+        let disburse = ExecuteMsg::Disburse { id: "pot".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), disburse).unwrap();
+        let cancel = ExecuteMsg::CancelStream { id: "pot".to_string() };
+        let res = execute(deps.as_mut(), env, mock_info("arbitrate", &[]), cancel).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(30, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "sponsor".to_string(), amount: coins(20, "atom") }),
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "token".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: "sponsor".to_string(),
+                        amount: Uint128::new(500),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+            ]
+        );
+        let err = query_contributors(deps.as_ref(), "pot".to_string()).unwrap_err();
+        assert!(matches!(err, StdError::NotFound { .. }));
+    }
+
+    #[test]
+    fn bounty_board_submissions() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+        let limits = ExecuteMsg::SetSubmissionLimits { cooldown_blocks: 5, max_per_bounty: Some(2) };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), limits).unwrap();
+
+        let create = CreateMsg {
+            id: "bug".to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "Fix issue in contract.rs".to_string(),
+            description: "some description".to_string(),
+            bounty: true,
+            ..Default::default()
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create)).unwrap();
+
+        let submit = |link: &str| ExecuteMsg::SubmitClaim { id: "bug".to_string(), link: link.to_string(), hunter: None };
+        let mut env = mock_env();
+        let commit = ExecuteMsg::CommitClaim { id: "bug".to_string(), commitment: Binary::from(vec![0u8; 32]) };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), commit).unwrap_err();
+        assert_eq!(err, ContractError::NotSealed {});
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), submit("https://fix/1")).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), submit("https://fix/2")).unwrap_err();
+        assert_eq!(err, ContractError::RateLimited {});
+        env.block.height += 5;
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), submit("https://fix/2")).unwrap();
+        env.block.height += 5;
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), submit("https://fix/3")).unwrap_err();
+        assert_eq!(err, ContractError::TooManySubmissions {});
 
-        app.execute_contract(
-            sender.clone(),
-            contract_addr.clone(),
-            &ExecuteMsg::Donate {},
-            &coins(10, "atom"),
-        )
-        .unwrap();
+        // bob submits on behalf of his team address
+        let msg = ExecuteMsg::SubmitClaim {
+            id: "bug".to_string(),
+            link: "https://fix/4".to_string(),
+            hunter: Some("team".to_string()),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
 
-        app.execute_contract(
-            owner.clone(),
-            contract_addr.clone(),
-            &ExecuteMsg::Withdraw {},
-            &[],
-        )
-        .unwrap();
+        let submissions = query_submissions(deps.as_ref(), "bug".to_string(), Some(0), None).unwrap().submissions;
+        assert_eq!(submissions.len(), 2);
+        assert_eq!(submissions[1].hunter, Addr::unchecked("team"));
+        assert_eq!(submissions[1].status, SubmissionStatus::Open);
+
+        // the arbiter rejects one and picks another, which pays out at once
+        let reject = ExecuteMsg::RejectSubmission { id: "bug".to_string(), submission_id: 0 };
+        execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), reject).unwrap();
+        // the source cannot pull the bounty while submissions wait for a decision
+        let cancel = ExecuteMsg::Cancel { id: "bug".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), cancel).unwrap_err();
+        assert_eq!(err, ContractError::OpenSubmissions {});
+        let pick = ExecuteMsg::PickWinner { id: "bug".to_string(), submission_id: 0 };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), pick).unwrap_err();
+        assert_eq!(err, ContractError::SubmissionNotOpen {});
 
+        let pick = ExecuteMsg::PickWinner { id: "bug".to_string(), submission_id: 2 };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), pick.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), pick).unwrap();
         assert_eq!(
-            app.wrap().query_all_balances(owner).unwrap(),
-            coins(10, "atom")
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "team".to_string(), amount: coins(100, "atom") })]
         );
-        assert_eq!(app.wrap().query_all_balances(sender).unwrap(), vec![]);
+        // the submissions go with the settled bounty
+        let submissions = query_submissions(deps.as_ref(), "bug".to_string(), None, None).unwrap().submissions;
+        assert!(submissions.is_empty());
+        assert!(!SUBMISSION_COUNTS.has(deps.as_ref().storage, ("bug", &Addr::unchecked("alice"))));
+
+        // a hunter named by someone else has to accept before the bounty pays out
+        let create = CreateMsg {
+            id: "typo".to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "Fix typo".to_string(),
+            description: "some description".to_string(),
+            bounty: true,
+            require_acceptance: true,
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(10, "atom")), ExecuteMsg::Create(create)).unwrap();
+        env.block.height += 5;
+        let msg = ExecuteMsg::SubmitClaim {
+            id: "typo".to_string(),
+            link: "https://fix/5".to_string(),
+            hunter: Some("team".to_string()),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+        let pick = ExecuteMsg::PickWinner { id: "typo".to_string(), submission_id: 0 };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), pick).unwrap();
+        assert!(res.messages.is_empty());
+        let accept = ExecuteMsg::AcceptRole { id: "typo".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("team", &[]), accept).unwrap();
+        let approve = ExecuteMsg::Approve { id: "typo".to_string() };
+        let res = execute(deps.as_mut(), env, mock_info("arbitrate", &[]), approve).unwrap();
         assert_eq!(
-            app.wrap().query_all_balances(contract_addr).unwrap(),
-            vec![]
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "team".to_string(), amount: coins(10, "atom") })]
         );
     }
 
     #[test]
-    fn set_recipient_after_creation() {
+    fn sealed_bounty_commit_and_reveal() {
         let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
 
-        // instantiate an empty contract
-        let instantiate_msg = InstantiateMsg {count: 17, minimal_donation: coin(10, "atom")};
-        let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "bug".to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "Fix issue in contract.rs".to_string(),
+            description: "some description".to_string(),
+            bounty: true,
+            sealed: true,
+            reveal_start: Some(Expiration::AtHeight(env.block.height + 10)),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create)).unwrap();
 
-        // create an escrow
-        let create: CreateMsg = CreateMsg {
-            id: "foobar".to_string(),
+        let submit = ExecuteMsg::SubmitClaim { id: "bug".to_string(), link: "https://fix/1".to_string(), hunter: None };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), submit).unwrap_err();
+        assert_eq!(err, ContractError::SealedBounty {});
+
+        let claim = SealedClaim {
+            id: "bug".to_string(),
+            link: "https://fix/1".to_string(),
+            hunter: "alice".to_string(),
+            salt: "pepper".to_string(),
+        };
+        let commitment = Binary::from(Sha256::digest(to_binary(&claim).unwrap().as_slice()).to_vec());
+        let commit = ExecuteMsg::CommitClaim { id: "bug".to_string(), commitment };
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), commit).unwrap();
+        // an unrevealed commitment doesn't keep the source from cancelling
+        assert_eq!(OPEN_SUBMISSIONS.may_load(deps.as_ref().storage, "bug").unwrap().unwrap_or_default(), 0);
+
+        // nothing can be revealed or picked during the commit phase
+        let reveal = |salt: &str| ExecuteMsg::RevealClaim {
+            id: "bug".to_string(),
+            submission_id: 0,
+            link: "https://fix/1".to_string(),
+            hunter: None,
+            salt: salt.to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), reveal("pepper")).unwrap_err();
+        assert_eq!(err, ContractError::RevealPhaseNotStarted {});
+        let pick = ExecuteMsg::PickWinner { id: "bug".to_string(), submission_id: 0 };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), pick.clone()).unwrap_err();
+        assert_eq!(err, ContractError::SubmissionNotOpen {});
+
+        let mut env = env;
+        env.block.height += 10;
+        let commit = ExecuteMsg::CommitClaim { id: "bug".to_string(), commitment: Binary::from(vec![0u8; 32]) };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), commit).unwrap_err();
+        assert_eq!(err, ContractError::CommitPhaseOver {});
+
+        let err = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), reveal("pepper")).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), reveal("salt")).unwrap_err();
+        assert_eq!(err, ContractError::InvalidReveal {});
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), reveal("pepper")).unwrap();
+        assert_eq!(OPEN_SUBMISSIONS.load(deps.as_ref().storage, "bug").unwrap(), 1);
+
+        let submissions = query_submissions(deps.as_ref(), "bug".to_string(), None, None).unwrap().submissions;
+        assert_eq!(submissions[0].link, "https://fix/1");
+        assert_eq!(submissions[0].status, SubmissionStatus::Open);
+        assert_eq!(submissions[0].revealed_at, Some(env.block.height));
+
+        let res = execute(deps.as_mut(), env, mock_info("arbitrate", &[]), pick).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "alice".to_string(), amount: coins(100, "atom") })]
+        );
+    }
+
+    #[test]
+    fn hashlock_release_and_refund() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let preimage = Binary::from(b"secret".to_vec());
+        let hashlock = Binary::from(Sha256::digest(preimage.as_slice()).to_vec());
+        let htlc = |id: &str| CreateMsg {
+            id: id.to_string(),
             arbiter: String::from("arbitrate"),
-            recipient: None,
-            title: "some_title".to_string(),
-            end_time: None,
-            end_height: Some(123456),
-            cw20_whitelist: None,
-            description: "some_description".to_string(),
+            recipient: Some(String::from("bob")),
+            title: "swap".to_string(),
+            description: "atoms for osmos".to_string(),
+            expires: Some(vec![Expiration::AtHeight(env.block.height + 100)]),
+            hashlock: Some(hashlock.clone()),
+            ..Default::default()
         };
-        let sender = String::from("source");
-        let balance = coins(100, "tokens");
-        let info = mock_info(&sender, &balance);
-        let msg = ExecuteMsg::Create(create.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create"), res.attributes[0]);
+        let mut no_deadline = htlc("bad");
+        no_deadline.expires = None;
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &coins(100, "atom")), ExecuteMsg::Create(no_deadline)).unwrap_err();
+        assert_eq!(err, ContractError::InvalidHashlock {});
+        let mut no_recipient = htlc("bad");
+        no_recipient.recipient = None;
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &coins(100, "atom")), ExecuteMsg::Create(no_recipient)).unwrap_err();
+        assert_eq!(err, ContractError::InvalidHashlock {});
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &coins(100, "atom")), ExecuteMsg::Create(htlc("swap"))).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &coins(50, "atom")), ExecuteMsg::Create(htlc("late"))).unwrap();
 
-        // ensure the details is what we expect
-        let details = query_detail(deps.as_ref(), "foobar".to_string()).unwrap();
+        // nor redirect it to someone else
+        let redirect = ExecuteMsg::SetRecipient { id: "swap".to_string(), recipient: "arbitrate".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), redirect).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // the arbiter cannot release it, only the preimage can
+        let approve = ExecuteMsg::Approve { id: "swap".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), approve).unwrap_err();
+        assert_eq!(err, ContractError::Hashlocked {});
+        let release = |id: &str, preimage: &[u8]| ExecuteMsg::Release { id: id.to_string(), preimage: Binary::from(preimage) };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), release("swap", b"guess")).unwrap_err();
+        assert_eq!(err, ContractError::InvalidPreimage {});
+
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), release("swap", b"secret")).unwrap();
+        assert!(res.attributes.contains(&attr("preimage", preimage.to_base64())));
         assert_eq!(
-            details,
-            DetailsResponse {
-                id: "foobar".to_string(),
-                arbiter: String::from("arbitrate"),
-                recipient: None,
-                source: String::from("source"),
-                title: "some_title".to_string(),
-                description: "some_description".to_string(),
-                end_height: Some(123456),
-                end_time: None,
-                native_balance: balance.clone(),
-                cw20_balance: vec![],
-                cw20_whitelist: vec![],
-            }
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "bob".to_string(), amount: coins(100, "atom") })]
         );
 
-        // approve it, should fail as we have not set recipient
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id });
-        match res {
-            Err(ContractError::RecipientNotSet {}) => {}
-            _ => panic!("Expect recipient not set error"),
-        }
+        // after the timelock the preimage is useless and anyone can refund the source
+        let mut env = env;
+        env.block.height += 100;
+        let err = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), release("late", b"secret")).unwrap_err();
+        assert_eq!(err, ContractError::Expired {});
+        let refund = ExecuteMsg::Refund { id: "late".to_string() };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), refund).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "alice".to_string(), amount: coins(50, "atom") })]
+        );
+    }
 
-        // test setting recipient not arbiter
-        let msg = ExecuteMsg::SetRecipient {
-            id: create.id.clone(),
-            recipient: "recp".to_string(),
+    #[test]
+    fn swap_settles_both_sides() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let swap = |id: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbitrate"),
+            title: "swap".to_string(),
+            description: "atoms for tokens".to_string(),
+            expires: Some(vec![Expiration::AtHeight(env.block.height + 100)]),
+            ask: Some(SwapAsk {
+                native: vec![],
+                cw20: vec![Cw20Coin { address: "my-cw20-token".to_string(), amount: Uint128::new(500) }],
+            }),
+            ..Default::default()
         };
-        let info = mock_info("someoneelse", &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, msg);
-        match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Expect unauthorized error"),
-        }
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &coins(100, "atom")), ExecuteMsg::Create(swap("swap"))).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &coins(50, "atom")), ExecuteMsg::Create(swap("unfilled"))).unwrap();
 
-        // test setting recipient valid
-        let msg = ExecuteMsg::SetRecipient {
-            id: create.id.clone(),
-            recipient: "recp".to_string(),
+        let approve = ExecuteMsg::Approve { id: "swap".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), approve).unwrap_err();
+        assert_eq!(err, ContractError::SwapEscrow {});
+        let top_up = ExecuteMsg::TopUp { id: "swap".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("carl", &coins(10, "atom")), top_up).unwrap_err();
+        assert_eq!(err, ContractError::SwapEscrow {});
+        // the filler receives the escrow, the arbiter cannot name anyone else
+        let redirect = ExecuteMsg::SetRecipient { id: "swap".to_string(), recipient: "arbitrate".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), redirect).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let fill = |amount: u128| {
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: String::from("bob"),
+                amount: Uint128::new(amount),
+                msg: to_binary(&ReceiveMsg::FillSwap { id: "swap".to_string() }).unwrap(),
+            })
         };
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(res.messages.len(), 0);
+        let err = execute(deps.as_mut(), env.clone(), mock_info("my-cw20-token", &[]), fill(400)).unwrap_err();
+        assert_eq!(err, ContractError::AskNotMet {});
+        let err = execute(deps.as_mut(), env.clone(), mock_info("other-token", &[]), fill(500)).unwrap_err();
+        assert_eq!(err, ContractError::AskNotMet {});
+
+        let res = execute(deps.as_mut(), env.clone(), mock_info("my-cw20-token", &[]), fill(500)).unwrap();
+        let transfer = Cw20ExecuteMsg::Transfer { recipient: "alice".to_string(), amount: Uint128::new(500) };
         assert_eq!(
-            res.attributes,
+            res.messages,
             vec![
-                attr("action", "set_recipient"),
-                attr("id", create.id.as_str()),
-                attr("recipient", "recp")
+                SubMsg::new(BankMsg::Send { to_address: "bob".to_string(), amount: coins(100, "atom") }),
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "my-cw20-token".to_string(),
+                    msg: to_binary(&transfer).unwrap(),
+                    funds: vec![],
+                }),
             ]
         );
 
-        // approve it, should now work with recp
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
-        assert_eq!(1, res.messages.len());
-        assert_eq!(("action", "approve"), res.attributes[0]);
+        // nobody filled the other one, so alice takes it back after the deadline
+        let mut env = env;
+        env.block.height += 100;
+        let refund = ExecuteMsg::Refund { id: "unfilled".to_string() };
+        let res = execute(deps.as_mut(), env, mock_info("alice", &[]), refund).unwrap();
         assert_eq!(
-            res.messages[0],
-            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
-                to_address: "recp".to_string(),
-                amount: balance,
-            }))
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "alice".to_string(), amount: coins(50, "atom") })]
         );
     }
 
     #[test]
-    fn add_tokens_proper() {
-        let mut tokens = GenericBalance::default();
-        tokens.add_tokens(Balance::from(vec![coin(123, "atom"), coin(789, "eth")]));
-        tokens.add_tokens(Balance::from(vec![coin(456, "atom"), coin(12, "btc")]));
+    fn approve_with_arbiter_signature() {
+        use bech32::{ToBase32, Variant};
+        use k256::ecdsa::{signature::Signer, Signature, SigningKey};
+        use k256::elliptic_curve::sec1::ToEncodedPoint;
+
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        // the arbiter is the address of a cold secp256k1 key
+        let key = SigningKey::from_bytes(&[7u8; 32]).unwrap();
+        let pubkey = Binary::from(key.verifying_key().to_encoded_point(true).as_bytes());
+        let hash = Ripemd160::digest(Sha256::digest(pubkey.as_slice()));
+        let arbiter = bech32::encode("cosmos", hash.to_base32(), Variant::Bech32).unwrap();
+
+        let create = CreateMsg {
+            id: "foobar".to_string(),
+            arbiter: arbiter.clone(),
+            recipient: Some(String::from("recd")),
+            title: "Some Title".to_string(),
+            description: "some description".to_string(),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create)).unwrap();
+
+        let sign = |key: &SigningKey, id: &str, nonce: u64| {
+            let approval = SignedApproval {
+                contract_address: mock_env().contract.address.to_string(),
+                chain_id: mock_env().block.chain_id,
+                id: id.to_string(),
+                action: "approve".to_string(),
+                nonce,
+            };
+            let signature: Signature = key.sign(to_binary(&approval).unwrap().as_slice());
+            Binary::from(signature.as_ref())
+        };
+        let approve = |signature: Binary, pubkey: Binary, nonce: u64| ExecuteMsg::ApproveWithSignature {
+            id: "foobar".to_string(),
+            signature,
+            pubkey,
+            nonce,
+        };
+
+        let msg = approve(sign(&key, "foobar", 1), pubkey.clone(), 1);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("relayer", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidNonce { expected: 0 });
+        let msg = approve(sign(&key, "other", 0), pubkey.clone(), 0);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("relayer", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidSignature {});
+
+        // a valid signature from a key that is not the arbiter's
+        let other = SigningKey::from_bytes(&[9u8; 32]).unwrap();
+        let other_pubkey = Binary::from(other.verifying_key().to_encoded_point(true).as_bytes());
+        let msg = approve(sign(&other, "foobar", 0), other_pubkey, 0);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("relayer", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let signature = sign(&key, "foobar", 0);
+        let res = execute(deps.as_mut(), mock_env(), mock_info("relayer", &[]), approve(signature.clone(), pubkey.clone(), 0)).unwrap();
         assert_eq!(
-            tokens.native,
-            vec![coin(579, "atom"), coin(789, "eth"), coin(12, "btc")]
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "recd".to_string(), amount: coins(100, "atom") })]
         );
+        let nonce: NonceResponse = from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Nonce { address: arbiter.clone() }).unwrap()).unwrap();
+        assert_eq!(nonce.nonce, 1);
+
+        // the same signature cannot be replayed on a new escrow under another id
+        let create = CreateMsg {
+            id: "again".to_string(),
+            arbiter: arbiter.clone(),
+            recipient: Some(String::from("recd")),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create)).unwrap();
+        let msg = ExecuteMsg::ApproveWithSignature { id: "again".to_string(), signature, pubkey, nonce: 0 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("relayer", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidNonce { expected: 1 });
     }
 
     #[test]
-    fn add_cw_tokens_proper() {
-        let mut tokens = GenericBalance::default();
-        let bar_token = Addr::unchecked("bar_token");
-        let foo_token = Addr::unchecked("foo_token");
-        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
-            address: foo_token.clone(),
-            amount: Uint128::new(12345),
-        }));
-        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
-            address: bar_token.clone(),
-            amount: Uint128::new(777),
-        }));
-        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
-            address: foo_token.clone(),
-            amount: Uint128::new(23400),
-        }));
+    fn arbiter_registry_and_ranking() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+        let policy = ExecuteMsg::SetArbiterPolicy { require_registered: true };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), policy.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), policy).unwrap();
+
+        for arbiter in ["judy", "quick"] {
+            let register = ExecuteMsg::RegisterArbiter {
+                name: arbiter.to_uppercase(),
+                contact_hash: Binary::from(Sha256::digest(arbiter.as_bytes()).to_vec()),
+                fee_terms: "1% of the escrow".to_string(),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(arbiter, &[]), register).unwrap();
+        }
+
+        let env = mock_env();
+        let create = |id: &str, arbiter: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: arbiter.to_string(),
+            recipient: Some(String::from("recd")),
+            expires: Some(vec![Expiration::AtHeight(env.block.height + 10)]),
+            ..Default::default()
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &coins(10, "atom")), ExecuteMsg::Create(create("a", "nobody"))).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterNotRegistered {});
+        for (id, arbiter) in [("a", "judy"), ("b", "judy"), ("c", "quick")] {
+            execute(deps.as_mut(), env.clone(), mock_info("source", &coins(10, "atom")), ExecuteMsg::Create(create(id, arbiter))).unwrap();
+        }
+
+        // quick approves after a minute, judy refunds one after the deadline and approves the other
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(60);
+        execute(deps.as_mut(), later.clone(), mock_info("quick", &[]), ExecuteMsg::Approve { id: "c".to_string() }).unwrap();
+        later.block.time = later.block.time.plus_seconds(240);
+        execute(deps.as_mut(), later.clone(), mock_info("judy", &[]), ExecuteMsg::Approve { id: "a".to_string() }).unwrap();
+        later.block.height += 10;
+        execute(deps.as_mut(), later, mock_info("judy", &[]), ExecuteMsg::Refund { id: "b".to_string() }).unwrap();
+
+        let judy = query_arbiter(deps.as_ref(), "judy".to_string()).unwrap();
+        assert_eq!(judy.profile.unwrap().name, "JUDY");
         assert_eq!(
-            tokens.cw20,
-            vec![
-                Cw20CoinVerified {
-                    address: foo_token,
-                    amount: Uint128::new(35745),
-                },
-                Cw20CoinVerified {
-                    address: bar_token,
-                    amount: Uint128::new(777),
-                }
-            ]
+            judy.stats,
+            ArbiterStats { approvals: 1, refunds: 1, disputes: 0, timed_decisions: 2, total_resolution_time: 600 }
         );
+        assert_eq!(judy.average_resolution_time, Some(300));
+
+        let ranked = query_ranked_arbiters(deps.as_ref(), None).unwrap().arbiters;
+        assert_eq!(ranked.iter().map(|a| a.address.as_str()).collect::<Vec<_>>(), vec!["judy", "quick"]);
+        let listed = query_arbiters(deps.as_ref(), Some("judy".to_string()), None).unwrap().arbiters;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].stats.decisions(), 1);
+
+        execute(deps.as_mut(), mock_env(), mock_info("quick", &[]), ExecuteMsg::UnregisterArbiter {}).unwrap();
+        let quick = query_arbiter(deps.as_ref(), "quick".to_string()).unwrap();
+        assert_eq!(quick.profile, None);
+        assert_eq!(quick.average_resolution_time, Some(60));
     }
 
     #[test]
-    fn top_up_mixed_tokens() {
+    fn arbiter_stake_unbond_and_slash() {
         let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+        let config = ExecuteMsg::SetStakingConfig {
+            denom: "atom".to_string(),
+            min_stake_bps: 1000,
+            unbonding_period: Duration::Height(10),
+            governance: Some("gov".to_string()),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), config).unwrap();
 
-        // instantiate an empty contract
-        let instantiate_msg = InstantiateMsg {count: 0, minimal_donation: coin(0, "atom")};
-        let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
-
-        // only accept these tokens
-        let whitelist = vec![String::from("bar_token"), String::from("foo_token")];
+        let err = execute(deps.as_mut(), mock_env(), mock_info("judy", &coins(5, "atom")), ExecuteMsg::Stake {}).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterNotRegistered {});
+        let register = ExecuteMsg::RegisterArbiter {
+            name: "Judy".to_string(),
+            contact_hash: Binary::from(vec![1u8; 32]),
+            fee_terms: "flat".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("judy", &[]), register).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("judy", &coins(5, "osmo")), ExecuteMsg::Stake {}).unwrap_err();
+        assert_eq!(err, ContractError::InvalidStake { denom: "atom".to_string() });
+        execute(deps.as_mut(), mock_env(), mock_info("judy", &coins(10, "atom")), ExecuteMsg::Stake {}).unwrap();
 
-        // create an escrow with 2 native tokens
-        let create = CreateMsg {
-            id: "foobar".to_string(),
-            arbiter: String::from("arbitrate"),
+        // a stake of 10 covers up to 100 across all escrows of the arbiter
+        let create = |id: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("judy"),
             recipient: Some(String::from("recd")),
-            title: "some_title".to_string(),
-            end_time: None,
-            end_height: None,
-            cw20_whitelist: Some(whitelist),
-            description: "some_description".to_string(),
+            challenge_period: Some(10),
+            appeal_arbiter: Some(String::from("appeals")),
+            ..Default::default()
         };
-        let sender = String::from("source");
-        let balance = vec![coin(100, "fee"), coin(200, "stake")];
-        let info = mock_info(&sender, &balance);
-        let msg = ExecuteMsg::Create(create.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create"), res.attributes[0]);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("source", &coins(110, "atom")), ExecuteMsg::Create(create("big"))).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientStake {});
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(40, "atom")), ExecuteMsg::Create(create("small"))).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("source", &coins(70, "atom")), ExecuteMsg::Create(create("other"))).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientStake {});
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(10, "atom")), TopUp { id: "small".to_string() }).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("source", &coins(60, "atom")), TopUp { id: "small".to_string() }).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientStake {});
 
-        // top it up with 2 more native tokens
-        let extra_native = vec![coin(250, "random"), coin(300, "stake")];
-        let info = mock_info(&sender, &extra_native);
-        let top_up = ExecuteMsg::TopUp {
-            id: create.id.clone(),
+        // the stake locked by open escrows cannot be unbonded
+        let env = mock_env();
+        let unbond = |amount: u128| ExecuteMsg::Unbond { amount: Uint128::new(amount) };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("judy", &[]), unbond(6)).unwrap_err();
+        assert_eq!(err, ContractError::StakeLocked {});
+        execute(deps.as_mut(), env.clone(), mock_info("judy", &[]), unbond(5)).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info("judy", &[]), ExecuteMsg::ClaimUnbonded {}).unwrap_err();
+        assert_eq!(err, ContractError::NothingToClaim {});
+
+        // slashing needs an upheld appeal against the arbiter
+        let slash = ExecuteMsg::Slash {
+            arbiter: "judy".to_string(),
+            id: "small".to_string(),
+            amount: Uint128::new(7),
         };
-        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "top_up"), res.attributes[0]);
+        let err = execute(deps.as_mut(), env.clone(), mock_info("gov", &[]), slash.clone()).unwrap_err();
+        assert_eq!(err, ContractError::NotSlashable {});
+        execute(deps.as_mut(), env.clone(), mock_info("judy", &[]), ExecuteMsg::Approve { id: "small".to_string() }).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info("source", &[]), ExecuteMsg::Appeal { id: "small".to_string() }).unwrap();
+        let decide = ExecuteMsg::DecideAppeal { id: "small".to_string(), approve: false };
+        execute(deps.as_mut(), env.clone(), mock_info("appeals", &[]), decide).unwrap();
 
-        // top up with one foreign token
-        let bar_token = String::from("bar_token");
-        let base = TopUp {
-            id: create.id.clone(),
+        // the closed escrow no longer locks stake, so more of it can be unbonded
+        execute(deps.as_mut(), env.clone(), mock_info("judy", &[]), unbond(3)).unwrap();
+
+        // bonded stake goes first, then unbonding stake, up to what the escrow locked
+        let err = execute(deps.as_mut(), env.clone(), mock_info("judy", &[]), slash.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), env.clone(), mock_info("gov", &[]), slash.clone()).unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(5, "atom") })]);
+        let stake: StakeResponse = from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Stake { address: "judy".to_string() }).unwrap()).unwrap();
+        assert_eq!(stake.bonded, Uint128::zero());
+        assert_eq!(stake.unbonding, vec![Unbonding { amount: Uint128::new(5), release_at: Expiration::AtHeight(env.block.height + 10) }]);
+        let err = execute(deps.as_mut(), env.clone(), mock_info("gov", &[]), slash).unwrap_err();
+        assert_eq!(err, ContractError::NotSlashable {});
+
+        // the denom stays while stake is outstanding
+        let config = ExecuteMsg::SetStakingConfig {
+            denom: "osmo".to_string(),
+            min_stake_bps: 1000,
+            unbonding_period: Duration::Height(10),
+            governance: Some("gov".to_string()),
         };
-        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: String::from("random"),
-            amount: Uint128::new(7890),
-            msg: to_binary(&base).unwrap(),
-        });
-        let info = mock_info(&bar_token, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "top_up"), res.attributes[0]);
+        let err = execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), config).unwrap_err();
+        assert_eq!(err, ContractError::StakesOutstanding {});
 
-        // top with a foreign token not on the whitelist
-        // top up with one foreign token
-        let baz_token = String::from("baz_token");
-        let base = TopUp {
-            id: create.id.clone(),
+        let mut env = env;
+        env.block.height += 10;
+        let res = execute(deps.as_mut(), env, mock_info("judy", &[]), ExecuteMsg::ClaimUnbonded {}).unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(BankMsg::Send { to_address: "judy".to_string(), amount: coins(5, "atom") })]);
+    }
+
+    #[test]
+    fn replace_inactive_arbiter() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        let create = CreateMsg {
+            id: "foobar".to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(String::from("recd")),
+            expires: Some(vec![Expiration::AtHeight(mock_env().block.height + 1000)]),
+            inactivity_window: Some(100),
+            backup_arbiter: Some(String::from("backup")),
+            ..Default::default()
         };
-        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: String::from("random"),
-            amount: Uint128::new(7890),
-            msg: to_binary(&base).unwrap(),
-        });
-        let info = mock_info(&baz_token, &[]);
-        let err = execute(deps.as_mut(), mock_env(), info, top_up).unwrap_err();
-        assert_eq!(err, ContractError::NotInWhitelist{});
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create)).unwrap();
 
-        // top up with second foreign token
-        let foo_token = String::from("foo_token");
-        let base = TopUp {
-            id: create.id.clone(),
+        let at = |blocks: u64| {
+            let mut env = mock_env();
+            env.block.height += blocks;
+            env
         };
-        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: String::from("random"),
-            amount: Uint128::new(888),
-            msg: to_binary(&base).unwrap(),
-        });
-        let info = mock_info(&foo_token, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "top_up"), res.attributes[0]);
+        let take_over = ExecuteMsg::TakeOverArbitration { id: "foobar".to_string() };
+        let err = execute(deps.as_mut(), at(50), mock_info("backup", &[]), take_over.clone()).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterActive {});
+        // the arbiter refunds only once the escrow expired, like anyone else
+        let refund = ExecuteMsg::Refund { id: "foobar".to_string() };
+        let err = execute(deps.as_mut(), at(50), mock_info("arbitrate", &[]), refund).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let recipients = ExecuteMsg::SetRecipients {
+            id: "foobar".to_string(),
+            recipients: vec![
+                RecipientShareMsg { address: "recd".to_string(), share: 5_000 },
+                RecipientShareMsg { address: "other".to_string(), share: 5_000 },
+            ],
+            dust_recipient: None,
+        };
+        execute(deps.as_mut(), at(50), mock_info("arbitrate", &[]), recipients).unwrap();
 
-        // approve it
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
-        assert_eq!(("action", "approve"), res.attributes[0]);
-        assert_eq!(3, res.messages.len());
+        // the window restarted with the arbiter's last action
+        let nominate = |arbiter: &str| ExecuteMsg::NominateArbiter { id: "foobar".to_string(), arbiter: arbiter.to_string() };
+        let err = execute(deps.as_mut(), at(120), mock_info("source", &[]), nominate("judy")).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterActive {});
+        let err = execute(deps.as_mut(), at(150), mock_info("anyone", &[]), nominate("judy")).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), at(150), mock_info("source", &[]), nominate("judy")).unwrap();
+        let res = execute(deps.as_mut(), at(150), mock_info("recd", &[]), nominate("quick")).unwrap();
+        assert!(res.events.is_empty());
 
-        // first message releases all native coins
+        // nominees have to be registered where the policy asks for it
+        let policy = |require_registered: bool| ExecuteMsg::SetArbiterPolicy { require_registered };
+        execute(deps.as_mut(), at(150), mock_info("owner", &[]), policy(true)).unwrap();
+        let err = execute(deps.as_mut(), at(150), mock_info("other", &[]), nominate("judy")).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterNotRegistered {});
+        execute(deps.as_mut(), at(150), mock_info("owner", &[]), policy(false)).unwrap();
+
+        // any split recipient speaks for the recipients
+        let res = execute(deps.as_mut(), at(150), mock_info("other", &[]), nominate("judy")).unwrap();
         assert_eq!(
-            res.messages[0],
-            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
-                to_address: create.recipient.clone().unwrap(),
-                amount: vec![coin(100, "fee"), coin(500, "stake"), coin(250, "random")],
-            }))
+            res.events,
+            vec![Event::new("arbiter_handover")
+                .add_attribute("id", "foobar")
+                .add_attribute("from", "arbitrate")
+                .add_attribute("to", "judy")
+                .add_attribute("reason", "joint_appointment")]
         );
+        let details = query_detail(deps.as_ref(), at(150), "foobar".to_string()).unwrap();
+        assert_eq!(details.arbiter, "judy");
+        assert_eq!(details.last_arbiter_action, at(150).block.height);
 
-        // second one release bar cw20 token
-        let send_msg = Cw20ExecuteMsg::Transfer {
-            recipient: create.recipient.clone().unwrap(),
-            amount: Uint128::new(7890),
+        // judy goes quiet as well, so the backup takes over
+        let err = execute(deps.as_mut(), at(200), mock_info("backup", &[]), take_over.clone()).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterActive {});
+        let res = execute(deps.as_mut(), at(250), mock_info("backup", &[]), take_over.clone()).unwrap();
+        assert_eq!(res.events[0].attributes[3], attr("reason", "backup"));
+        let details = query_detail(deps.as_ref(), at(250), "foobar".to_string()).unwrap();
+        assert_eq!((details.arbiter.as_str(), details.backup_arbiter), ("backup", None));
+
+        // without any arbiter the source can still refund after expiry
+        let refund = ExecuteMsg::Refund { id: "foobar".to_string() };
+        let err = execute(deps.as_mut(), at(999), mock_info("source", &[]), refund.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), at(1000), mock_info("source", &[]), refund).unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "atom") })]);
+    }
+
+    #[test]
+    fn appeal_pending_decisions() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        let create = |id: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(String::from("recd")),
+            challenge_period: Some(10),
+            appeal_arbiter: Some(String::from("court")),
+            appeal_bond: Some(coin(5, "atom")),
+            ..Default::default()
         };
+        let mut no_court = create("bad");
+        no_court.appeal_arbiter = None;
+        let err = execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(no_court)).unwrap_err();
+        assert_eq!(err, ContractError::NoAppealArbiter {});
+        for id in ["appealed", "final", "lapsed"] {
+            execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create(id))).unwrap();
+            let res = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), ExecuteMsg::Approve { id: id.to_string() }).unwrap();
+            assert!(res.messages.is_empty());
+        }
+        let details = query_detail(deps.as_ref(), mock_env(), "final".to_string()).unwrap();
         assert_eq!(
-            res.messages[1],
-            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: bar_token,
-                msg: to_binary(&send_msg).unwrap(),
-                funds: vec![]
-            }))
+            details.pending_decision,
+            Some(PendingDecision { decision: Decision::Approve, height: mock_env().block.height, appeal: None })
         );
 
-        // third one release foo cw20 token
-        let send_msg = Cw20ExecuteMsg::Transfer {
-            recipient: create.recipient.unwrap(),
-            amount: Uint128::new(888),
+        let at = |blocks: u64| {
+            let mut env = mock_env();
+            env.block.height += blocks;
+            env
         };
+        let finalize = |id: &str| ExecuteMsg::FinalizeDecision { id: id.to_string() };
+        let err = execute(deps.as_mut(), at(9), mock_info("anyone", &[]), finalize("final")).unwrap_err();
+        assert_eq!(err, ContractError::ChallengePeriodActive {});
+        let res = execute(deps.as_mut(), at(10), mock_info("anyone", &[]), finalize("final")).unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(BankMsg::Send { to_address: "recd".to_string(), amount: coins(100, "atom") })]);
+        let settled = query_settled(deps.as_ref(), "final".to_string()).unwrap().settlement;
+        assert_eq!(settled.actor, Addr::unchecked("anyone"));
+
+        // the source appeals the other approval and wins
+        let appeal = ExecuteMsg::Appeal { id: "appealed".to_string() };
+        let err = execute(deps.as_mut(), at(5), mock_info("source", &coins(1, "atom")), appeal.clone()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidAppealBond { bond: "5atom".to_string() });
+        let err = execute(deps.as_mut(), at(10), mock_info("source", &coins(5, "atom")), appeal.clone()).unwrap_err();
+        assert_eq!(err, ContractError::ChallengePeriodOver {});
+        execute(deps.as_mut(), at(5), mock_info("source", &coins(5, "atom")), appeal).unwrap();
+        let err = execute(deps.as_mut(), at(14), mock_info("anyone", &[]), finalize("appealed")).unwrap_err();
+        assert_eq!(err, ContractError::AppealPending {});
+
+        let decide = ExecuteMsg::DecideAppeal { id: "appealed".to_string(), approve: false };
+        let err = execute(deps.as_mut(), at(14), mock_info("arbitrate", &[]), decide.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), at(14), mock_info("court", &[]), decide).unwrap();
+        assert!(res.attributes.contains(&attr("appeal", "upheld")));
         assert_eq!(
-            res.messages[2],
-            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: foo_token,
-                msg: to_binary(&send_msg).unwrap(),
-                funds: vec![]
-            }))
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(5, "atom") }),
+            ]
+        );
+
+        // an appeal the court leaves undecided lapses, the decision stands and the bond goes back
+        let appeal = ExecuteMsg::Appeal { id: "lapsed".to_string() };
+        execute(deps.as_mut(), at(5), mock_info("source", &coins(5, "atom")), appeal).unwrap();
+        let err = execute(deps.as_mut(), at(14), mock_info("anyone", &[]), finalize("lapsed")).unwrap_err();
+        assert_eq!(err, ContractError::AppealPending {});
+        let res = execute(deps.as_mut(), at(15), mock_info("anyone", &[]), finalize("lapsed")).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "recd".to_string(), amount: coins(100, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(5, "atom") }),
+            ]
         );
+        let stats = query_arbiter(deps.as_ref(), "arbitrate".to_string()).unwrap().stats;
+        assert_eq!((stats.approvals, stats.disputes), (2, 2));
     }
 
     #[test]
-    pub fn happy_path_native(){
+    fn mutual_approve_and_refund() {
         let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
 
-        let instantiate_msg = InstantiateMsg{ count: 0, minimal_donation: coin(0, "atom")};
-        let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(res.messages.len(), 0);
-
-        let create = CreateMsg{
-            id: "foobar".to_string(),
+        let create = |id: &str| CreateMsg {
+            id: id.to_string(),
             arbiter: String::from("arbitrate"),
             recipient: Some(String::from("recd")),
-            title: "some title".to_string(),
-            description: "some description".to_string(),
-            end_height: Some(123456),
-            end_time: None,
-            cw20_whitelist: None,
+            ..Default::default()
         };
-        let sender = String:: from("source");
-        let balance = coins(100, "otms");
-        let info = mock_info(&sender, &balance);
-        let msg = ExecuteMsg::Create(create.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create"), res.attributes[0]);
+        for id in ["agreed", "undone"] {
+            execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create(id))).unwrap();
+        }
+
+        let approve = ExecuteMsg::MutualApprove { id: "agreed".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), approve.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), mock_env(), mock_info("source", &[]), approve.clone()).unwrap();
+        assert!(res.messages.is_empty());
+        let details = query_detail(deps.as_ref(), mock_env(), "agreed".to_string()).unwrap();
+        assert_eq!(details.consents, vec![Consent { party: Addr::unchecked("source"), decision: Decision::Approve }]);
+        let res = execute(deps.as_mut(), mock_env(), mock_info("recd", &[]), approve).unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(BankMsg::Send { to_address: "recd".to_string(), amount: coins(100, "atom") })]);
+
+        // nothing happens while the parties disagree
+        let refund = ExecuteMsg::MutualRefund { id: "undone".to_string() };
+        let approve = ExecuteMsg::MutualApprove { id: "undone".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &[]), approve).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("recd", &[]), refund.clone()).unwrap();
+        assert!(res.messages.is_empty());
+
+        // a new recipient starts over
+        let set_recipient = ExecuteMsg::SetRecipient { id: "undone".to_string(), recipient: "other".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), set_recipient).unwrap();
+        let details = query_detail(deps.as_ref(), mock_env(), "undone".to_string()).unwrap();
+        assert_eq!(details.consents, vec![]);
+        execute(deps.as_mut(), mock_env(), mock_info("other", &[]), refund.clone()).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("source", &[]), refund).unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "atom") })]);
+    }
+
+    #[test]
+    fn cancel_before_recipient_is_set() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        let create = |id: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbitrate"),
+            lock_in: Some(Duration::Height(5)),
+            ..Default::default()
+        };
+        for id in ["first", "second"] {
+            execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create(id))).unwrap();
+        }
+
+        let at = |blocks: u64| {
+            let mut env = mock_env();
+            env.block.height += blocks;
+            env
+        };
+        let cancel = |id: &str| ExecuteMsg::Cancel { id: id.to_string() };
+        let set_recipient = |id: &str| ExecuteMsg::SetRecipient { id: id.to_string(), recipient: "recd".to_string() };
+        let err = execute(deps.as_mut(), at(4), mock_info("source", &[]), cancel("first")).unwrap_err();
+        assert_eq!(err, ContractError::LockedIn {});
+        let err = execute(deps.as_mut(), at(5), mock_info("arbitrate", &[]), cancel("first")).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // once the recipient is set the source can no longer back out
+        execute(deps.as_mut(), at(5), mock_info("arbitrate", &[]), set_recipient("first")).unwrap();
+        let err = execute(deps.as_mut(), at(5), mock_info("source", &[]), cancel("first")).unwrap_err();
+        assert_eq!(err, ContractError::RecipientAlreadySet {});
 
-        let details = query_detail(deps.as_ref(), "foobar".to_string()).unwrap();
+        // and a cancelled escrow can no longer get a recipient
+        let res = execute(deps.as_mut(), at(5), mock_info("source", &[]), cancel("second")).unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "atom") })]);
+        let err = execute(deps.as_mut(), at(5), mock_info("arbitrate", &[]), set_recipient("second")).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+    }
+
+    #[test]
+    fn create_from_template() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        let defaults = CreateMsg {
+            arbiter: String::from("judy"),
+            title: "Sprint payout".to_string(),
+            cw20_whitelist: Some(vec![String::from("token")]),
+            expires_in: Some(vec![Duration::Height(100)]),
+            recipients: Some(vec![
+                RecipientShareMsg { address: "dev".to_string(), share: 9000 },
+                RecipientShareMsg { address: "lead".to_string(), share: 1000 },
+            ]),
+            ..Default::default()
+        };
+        let register = ExecuteMsg::RegisterTemplate { name: "sprint".to_string(), defaults: defaults.clone() };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), register.clone()).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), register).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // the split comes from the template, the title is overridden
+        let create = |id: &str, overrides: TemplateOverrides| ExecuteMsg::CreateFromTemplate {
+            template: "sprint".to_string(),
+            id: id.to_string(),
+            version: Some(1),
+            overrides,
+        };
+        let overrides = TemplateOverrides { title: Some("Sprint 12".to_string()), ..Default::default() };
+        execute(deps.as_mut(), mock_env(), mock_info("bob", &coins(100, "atom")), create("s12", overrides)).unwrap();
+        let details = query_detail(deps.as_ref(), mock_env(), "s12".to_string()).unwrap();
+        assert_eq!((details.arbiter.as_str(), details.title.as_str()), ("judy", "Sprint 12"));
+        assert_eq!(details.expires, vec![Expiration::AtHeight(mock_env().block.height + 100)]);
+        assert_eq!(details.recipients.len(), 2);
+        assert_eq!(details.cw20_whitelist, vec!["token".to_string()]);
+
+        // a single recipient replaces the split, and the overrides are checked like any escrow
+        let overrides = TemplateOverrides { recipient: Some("solo".to_string()), ..Default::default() };
+        execute(deps.as_mut(), mock_env(), mock_info("bob", &coins(100, "atom")), create("s13", overrides)).unwrap();
+        let details = query_detail(deps.as_ref(), mock_env(), "s13".to_string()).unwrap();
+        assert_eq!((details.recipient, details.recipients), (Some("solo".to_string()), vec![]));
+        let overrides = TemplateOverrides { expires: Some(vec![Expiration::AtHeight(1)]), ..Default::default() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("bob", &coins(100, "atom")), create("s14", overrides)).unwrap_err();
+        assert_eq!(err, ContractError::DeadlineInPast {});
+
+        // an update is a new version, creators pinned to the old one are stopped
+        let updated = CreateMsg { arbiter: String::from("mallory"), ..defaults.clone() };
+        let register = ExecuteMsg::RegisterTemplate { name: "sprint".to_string(), defaults: updated.clone() };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), register).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("bob", &coins(100, "atom")), create("s15", TemplateOverrides::default())).unwrap_err();
+        assert_eq!(err, ContractError::TemplateChanged { version: 2 });
+
+        let templates = query_templates(deps.as_ref(), None, None).unwrap().templates;
         assert_eq!(
-            details,
-            DetailsResponse {
-                id: "foobar".to_string(),
+            templates,
+            vec![TemplateResponse { name: "sprint".to_string(), owner: "alice".to_string(), version: 2, defaults: updated }]
+        );
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::RemoveTemplate { name: "sprint".to_string() }).unwrap();
+        assert!(query_template(deps.as_ref(), "sprint".to_string()).is_err());
+    }
+
+    #[test]
+    fn batch_merges_payouts() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        for id in ["a", "b", "c"] {
+            let create = CreateMsg {
+                id: id.to_string(),
                 arbiter: String::from("arbitrate"),
                 recipient: Some(String::from("recd")),
-                source: String::from("source"),
-                title: "some title".to_string(),
-                description: "some description".to_string(),
-                end_height: Some(123456),
-                end_time: None,
-                native_balance: balance.clone(),
-                cw20_balance: vec![],
-                cw20_whitelist: vec![],
-            }
-        );
+                ..Default::default()
+            };
+            execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create)).unwrap();
+        }
 
-        //approve it
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res=  execute(deps.as_mut(),mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
-        assert_eq!(1, res.messages.len());
-        assert_eq!(("action", "approve"), res.attributes[0]);
+        let approve = |id: &str| EscrowAction::Approve { id: id.to_string() };
+        let actions = vec![
+            approve("a"),
+            approve("b"),
+            approve("missing"),
+            EscrowAction::TopUp { id: "c".to_string(), amount: coins(50, "atom") },
+            EscrowAction::TopUp { id: "c".to_string(), amount: coins(50, "atom") },
+        ];
+        let msg = ExecuteMsg::Batch { actions, mode: BatchMode::BestEffort };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &coins(80, "atom")), msg).unwrap();
+        // both approvals go out as a single transfer and the unallocated funds are returned
         assert_eq!(
-            res.messages[0],
-            SubMsg::new(CosmosMsg::Bank(BankMsg::Send { 
-                to_address: create.recipient.unwrap(),
-                amount: balance,
-            }))
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "recd".to_string(), amount: coins(200, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "arbitrate".to_string(), amount: coins(30, "atom") }),
+            ]
         );
+        let results = from_binary::<BatchResponse>(&res.data.unwrap()).unwrap().results;
+        let failed: Vec<_> = results.iter().filter(|r| r.error.is_some()).map(|r| r.id.as_str()).collect();
+        assert_eq!((results.len(), failed), (5, vec!["missing", "c"]));
+        assert_eq!(results[4].error, Some(ContractError::TopUpNotCovered {}.to_string()));
+        let details = query_detail(deps.as_ref(), mock_env(), "c".to_string()).unwrap();
+        assert_eq!(details.native_balance, coins(150, "atom"));
 
-        //second attempt fails (not found)
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap_err();
-        assert!(matches!(err, ContractError::Std(StdError::NotFound{..})))
+        // a single failure rejects the whole batch
+        let actions = vec![approve("c"), approve("a")];
+        let msg = ExecuteMsg::Batch { actions, mode: BatchMode::AllOrNothing };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::BatchFailed { index: 1, .. }));
+    }
+
+    #[test]
+    fn batch_discards_writes_of_failed_items() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+        let create = CreateMsg {
+            id: "a".to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(String::from("recd")),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create)).unwrap();
+
+        // a handler that wrote and then failed leaves the storage as it was
+        let snapshot: Vec<_> = deps.storage.range(None, None, Order::Ascending).collect();
+        let mut cache = StorageCache::new(&mut deps.storage);
+        let mut escrow = ESCROWS.load(&cache, "a").unwrap();
+        escrow.recipient = Some(Addr::unchecked("mallory"));
+        ESCROWS.save(&mut cache, "a", &escrow).unwrap();
+        ESCROWS.remove(&mut cache, "a");
+        ESCROWS.save(&mut cache, "z", &escrow).unwrap();
+        // reads see the cached writes
+        let ids: Vec<_> = ESCROWS.keys(&cache, None, None, Order::Ascending).collect::<StdResult<_>>().unwrap();
+        assert_eq!(ids, vec!["z".to_string()]);
+        drop(cache);
+        assert_eq!(deps.storage.range(None, None, Order::Ascending).collect::<Vec<_>>(), snapshot);
 
+        let actions = vec![
+            EscrowAction::Refund { id: "a".to_string() },
+            EscrowAction::Approve { id: "missing".to_string() },
+            EscrowAction::SetRecipient { id: "a".to_string(), recipient: "mallory".to_string() },
+        ];
+        let msg = ExecuteMsg::Batch { actions, mode: BatchMode::BestEffort };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("source", &[]), msg).unwrap();
+        let results = from_binary::<BatchResponse>(&res.data.unwrap()).unwrap().results;
+        assert!(results.iter().all(|r| r.error.is_some()));
+        assert_eq!(deps.storage.range(None, None, Order::Ascending).collect::<Vec<_>>(), snapshot);
     }
 
     #[test]
-    pub fn happy_path_cw20(){
+    fn stats_follow_escrow_lifecycle() {
         let mut deps = mock_dependencies();
-        let instantiate_msg = InstantiateMsg{count: 0, minimal_donation:coin(0, "atom")};
-        let info = mock_info(&String::from("anyone"), &[]);
-        let rest = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, rest.messages.len());
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(1, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
 
-        //create escrow
-        let create = CreateMsg{
-            id: "foobar".to_string(),
+        let create = |id: &str| CreateMsg {
+            id: id.to_string(),
             arbiter: String::from("arbitrate"),
             recipient: Some(String::from("recd")),
-            title: "Some Title".to_string(),
-            description: "some description".to_string(),
-            end_height: None,
-            end_time: None,
-            cw20_whitelist: Some(vec![String::from("other-token")]),
+            expires: Some(vec![Expiration::AtHeight(mock_env().block.height + 10)]),
+            ..Default::default()
         };
-        let receive = Cw20ReceiveMsg{
-            sender: String::from("source"),
-            amount: Uint128::new(100),
-            msg: to_binary(&ExecuteMsg::Create(create.clone())).unwrap(),
+        for id in ["paid", "late"] {
+            execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create(id))).unwrap();
+        }
+        let top_up = ExecuteMsg::TopUp { id: "late".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(50, "btc")), top_up).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), ExecuteMsg::Approve { id: "paid".to_string() }).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("fan", &coins(5, "atom")), ExecuteMsg::Donate {}).unwrap();
+
+        let stats = query_stats(deps.as_ref(), mock_env()).unwrap();
+        let expected = StatsResponse {
+            open: 1,
+            expired_unclaimed: 0,
+            created: 2,
+            approved: 1,
+            refunded: 0,
+            locked_native: vec![coin(100, "atom"), coin(50, "btc")],
+            locked_cw20: vec![],
+            donated: coins(5, "atom"),
         };
-        let token_contract = String::from("my-cw20-token");
-        let info = mock_info(&token_contract, &[]);
-        let msg = ExecuteMsg::Receive(receive.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create"), res.attributes[0]);
+        assert_eq!(stats, expected);
 
-        //ensure the whitelist is what we expect
-        let details = query_detail(deps.as_ref(), "foobar".to_string()).unwrap();
+        // the remaining escrow counts as unclaimed once its deadline passes, until it is refunded
+        let mut env = mock_env();
+        env.block.height += 10;
+        let stats = query_stats(deps.as_ref(), env.clone()).unwrap();
+        assert_eq!((stats.open, stats.expired_unclaimed), (1, 1));
+        execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), ExecuteMsg::Refund { id: "late".to_string() }).unwrap();
+        let stats = query_stats(deps.as_ref(), env).unwrap();
         assert_eq!(
-            details,
-            DetailsResponse {
-                id: "foobar".to_string(),
-                arbiter: String::from("arbitrate"),
-                recipient: Some(String::from("recd")),
-                source: String::from("source"),
-                title: "Some Title".to_string(),
-                description: "some description".to_string(),
-                end_height: None,
-                end_time: None,
-                native_balance: vec![],
-                cw20_balance: vec![Cw20Coin {
-                    address: String::from("my-cw20-token"),
-                    amount: Uint128::new(100),
-                }],
-                cw20_whitelist: vec![String::from("other-token"), String::from("my-cw20-token")],
-            }
+            (stats.open, stats.expired_unclaimed, stats.refunded, stats.locked_native),
+            (0, 0, 1, vec![])
         );
+    }
 
-        //approve it
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
-        assert_eq!(1, res.messages.len());
-        assert_eq!(("action", "approve"), res.attributes[0]);
-        let send_msg = Cw20ExecuteMsg::Transfer { recipient: create.recipient.unwrap(), amount: receive.amount };
+    #[test]
+    fn sweep_expired_pays_keeper_tip() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+        let set_tip = ExecuteMsg::SetKeeperTip { tip: Some(coin(2, "atom")) };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("keeper", &[]), set_tip.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let contract_height = mock_env().block.height;
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), set_tip).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &coins(3, "atom")), ExecuteMsg::FundKeeperReserve {}).unwrap();
+
+        let create = |id: &str, expires: Expiration| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(String::from("recd")),
+            expires: Some(vec![expires]),
+            ..Default::default()
+        };
+        let height = mock_env().block.height;
+        let escrows = [
+            ("third", Expiration::AtHeight(height + 8)),
+            ("first", Expiration::AtHeight(height + 2)),
+            ("open", Expiration::AtHeight(height + 50)),
+            ("second", Expiration::AtHeight(height + 5)),
+        ];
+        for (id, expires) in escrows {
+            execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create(id, expires))).unwrap();
+        }
+
+        // the oldest deadlines go first, refunds to the same source are paid in one message
+        let mut env = mock_env();
+        env.block.height += 10;
+        let sweep = ExecuteMsg::SweepExpired { limit: 2 };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), sweep.clone()).unwrap();
         assert_eq!(
-            res.messages[0],
-            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: token_contract,
-                msg: to_binary(&send_msg).unwrap(),
-                funds: vec![]
-            }))
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(200, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "keeper".to_string(), amount: coins(3, "atom") }),
+            ]
         );
+        let swept = from_binary::<SweepResponse>(&res.data.unwrap()).unwrap().swept;
+        assert_eq!(swept, vec!["first".to_string(), "second".to_string()]);
 
-        // second attempt fails (not found)
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap_err();
-        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+        // once the reserve is used up sweeping goes on without a tip
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), sweep.clone()).unwrap();
+        assert_eq!(res.messages, vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "atom") })]);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), sweep.clone()).unwrap();
+        assert!(res.messages.is_empty());
+        assert!(from_binary::<SweepResponse>(&res.data.unwrap()).unwrap().swept.is_empty());
+        assert!(query_detail(deps.as_ref(), mock_env(), "open".to_string()).is_ok());
+
+        // a passed deadline that is not enough with ExpiryMode::All is only looked at once
+        let both = CreateMsg {
+            expires: Some(vec![Expiration::AtHeight(contract_height + 2), Expiration::AtTime(mock_env().block.time.plus_seconds(1000))]),
+            expiry_mode: Some(ExpiryMode::All),
+            ..create("both", Expiration::Never {})
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(both)).unwrap();
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), sweep.clone()).unwrap();
+        assert!(from_binary::<SweepResponse>(&res.data.unwrap()).unwrap().swept.is_empty());
+        assert!(!EXPIRING_AT_HEIGHT.has(&deps.storage, (contract_height + 2, "both")));
+        let mut env = mock_env();
+        env.block.height += 50;
+        env.block.time = env.block.time.plus_seconds(1000);
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), sweep).unwrap();
+        let swept = from_binary::<SweepResponse>(&res.data.unwrap()).unwrap().swept;
+        assert_eq!(swept, vec!["open".to_string(), "both".to_string()]);
 
+        // dust escrows and the keeper's own don't earn the full tip
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &coins(10, "atom")), ExecuteMsg::FundKeeperReserve {}).unwrap();
+        let expires = Expiration::AtHeight(contract_height + 55);
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(1, "atom")), ExecuteMsg::Create(create("dust", expires))).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("keeper", &coins(100, "atom")), ExecuteMsg::Create(create("own", expires))).unwrap();
+        let mut env = mock_env();
+        env.block.height += 60;
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), ExecuteMsg::SweepExpired { limit: 5 }).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(1, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "keeper".to_string(), amount: coins(101, "atom") }),
+            ]
+        );
     }
 
+    #[test]
+    fn settled_escrows_are_archived() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        let create = |id: &str, recipient: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbitrate"),
+            recipient: Some(recipient.to_string()),
+            expires: Some(vec![Expiration::AtHeight(mock_env().block.height + 1)]),
+            ..Default::default()
+        };
+        for (id, recipient) in [("paid", "alice"), ("refunded", "bob")] {
+            execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create(id, recipient))).unwrap();
+        }
+        let top_up = ExecuteMsg::TopUp { id: "paid".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("fan", &coins(10, "atom")), top_up).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), ExecuteMsg::Approve { id: "paid".to_string() }).unwrap();
+        let mut env = mock_env();
+        env.block.height += 1;
+        execute(deps.as_mut(), env.clone(), mock_info("arbitrate", &[]), ExecuteMsg::Refund { id: "refunded".to_string() }).unwrap();
+
+        // the details are gone, the archive keeps how the escrow ended
+        assert!(query_detail(deps.as_ref(), mock_env(), "paid".to_string()).is_err());
+        let settled = query_settled(deps.as_ref(), "refunded".to_string()).unwrap();
+        let expected = Settlement {
+            outcome: Decision::Refund,
+            parties: vec![Addr::unchecked("source"), Addr::unchecked("bob"), Addr::unchecked("arbitrate")],
+            payouts: vec![Payout {
+                to: "source".to_string(),
+                amount: GenericBalance { native: coins(100, "atom"), cw20: vec![] },
+            }],
+            height: env.block.height,
+            time: env.block.time,
+            actor: Addr::unchecked("arbitrate"),
+        };
+        assert_eq!(settled, SettledResponse { id: "refunded".to_string(), settlement: expected });
+
+        let history = |party: &str, start_after: Option<String>| {
+            query_settled_history(deps.as_ref(), party.to_string(), start_after, None).unwrap().escrows
+        };
+        let ids = |escrows: Vec<SettledResponse>| escrows.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids(history("source", None)), vec!["paid".to_string(), "refunded".to_string()]);
+        assert_eq!(ids(history("source", Some("paid".to_string()))), vec!["refunded".to_string()]);
+        let paid = history("alice", None);
+        assert_eq!(paid[0].settlement.outcome, Decision::Approve);
+        assert_eq!(paid[0].settlement.payouts[0].to, "alice");
+        assert_eq!(history("bob", None).len(), 1);
+        // funders that were not paid are parties as well
+        assert_eq!(ids(history("fan", None)), vec!["paid".to_string()]);
+
+        // a settled id can't be used again, which would bury its settlement
+        let msg = ExecuteMsg::Create(create("paid", "carol"));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), msg).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyInUse {});
+    }
 }
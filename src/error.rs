@@ -25,5 +25,197 @@ pub enum ContractError {
     Expired {},
 
     #[error("Recipient is not set")]
-    RecipientNotSet {}
+    RecipientNotSet {},
+
+    #[error("Escrow has no stream")]
+    NotAStream {},
+
+    #[error("Stream period must be greater than zero")]
+    InvalidPeriod {},
+
+    #[error("No stream payments are due")]
+    NothingDue {},
+
+    #[error("Amendment does not change anything")]
+    NoChanges {},
+
+    #[error("Deadline must be in the future")]
+    DeadlineInPast {},
+
+    #[error("Deadline lies too far in the future")]
+    DeadlineOverflow {},
+
+    #[error("Escrow changed since the amendment was proposed")]
+    StaleAmendment {},
+
+    #[error("Another amendment is still pending")]
+    AmendmentPending {},
+
+    #[error("Set at most one deadline per kind, height or time")]
+    ConflictingDeadlines {},
+
+    #[error("Escrow duration is below the minimum")]
+    DurationTooShort {},
+
+    #[error("Escrow duration is above the maximum")]
+    DurationTooLong {},
+
+    #[error("Recipient has not accepted its role")]
+    RecipientNotAccepted {},
+
+    #[error("Recipient shares must be unique, non-zero and sum up to 10000 basis points")]
+    InvalidShares {},
+
+    #[error("Set either a recipient or a list of recipients, not both")]
+    ConflictingRecipients {},
+
+    #[error("Escrow is not a bounty")]
+    NotABounty {},
+
+    #[error("Bounty no longer takes submissions")]
+    BountyClosed {},
+
+    #[error("Submitting too often, wait for the cooldown")]
+    RateLimited {},
+
+    #[error("Too many submissions to this bounty")]
+    TooManySubmissions {},
+
+    #[error("Submission is no longer open")]
+    SubmissionNotOpen {},
+
+    #[error("Bounty only takes sealed submissions")]
+    SealedBounty {},
+
+    #[error("Bounty only takes open submissions")]
+    NotSealed {},
+
+    #[error("Commit phase is over")]
+    CommitPhaseOver {},
+
+    #[error("Reveal phase has not started")]
+    RevealPhaseNotStarted {},
+
+    #[error("Revealed submission does not match the commitment")]
+    InvalidReveal {},
+
+    #[error("Hashlock must be a 32 byte SHA-256 hash and needs a deadline and a recipient")]
+    InvalidHashlock {},
+
+    #[error("Escrow is hash-locked, release it with the preimage")]
+    Hashlocked {},
+
+    #[error("Escrow is not hash-locked")]
+    NotHashlocked {},
+
+    #[error("Preimage does not match the hashlock")]
+    InvalidPreimage {},
+
+    #[error("Swap ask must be native coins or a single cw20 token and needs a deadline")]
+    InvalidAsk {},
+
+    #[error("Escrow is not a swap")]
+    NotASwap {},
+
+    #[error("Swap escrows settle when filled")]
+    SwapEscrow {},
+
+    #[error("Deposit does not match the ask of the swap")]
+    AskNotMet {},
+
+    #[error("Public key must be a compressed secp256k1 or an ed25519 key")]
+    InvalidPubkey {},
+
+    #[error("Signature does not verify")]
+    InvalidSignature {},
+
+    #[error("Nonce must be {expected}")]
+    InvalidNonce { expected: u64 },
+
+    #[error("Escrow is not resolved by a contract")]
+    NoResolver {},
+
+    #[error("Resolver has not decided yet")]
+    ResolutionPending {},
+
+    #[error("Arbiter is not registered")]
+    ArbiterNotRegistered {},
+
+    #[error("Staking is not configured")]
+    StakingDisabled {},
+
+    #[error("Stake must be sent in {denom} only")]
+    InvalidStake { denom: String },
+
+    #[error("Arbiter stake is below the minimum for this escrow")]
+    InsufficientStake {},
+
+    #[error("Stake is locked by open escrows")]
+    StakeLocked {},
+
+    #[error("No upheld appeal against the arbiter on this escrow to slash for")]
+    NotSlashable {},
+
+    #[error("Staking denom cannot change while stake is bonded or unbonding")]
+    StakesOutstanding {},
+
+    #[error("No unbonded stake is ready to claim")]
+    NothingToClaim {},
+
+    #[error("Arbiter is still active")]
+    ArbiterActive {},
+
+    #[error("Challenge period needs an appeal arbiter")]
+    NoAppealArbiter {},
+
+    #[error("Escrow has a pending decision")]
+    DecisionPending {},
+
+    #[error("Template was updated, it is at version {version} now")]
+    TemplateChanged { version: u64 },
+
+    #[error("Bounty has submissions waiting for the arbiter")]
+    OpenSubmissions {},
+
+    #[error("Escrow has no pending decision")]
+    NoPendingDecision {},
+
+    #[error("Challenge period is over")]
+    ChallengePeriodOver {},
+
+    #[error("Challenge period is not over")]
+    ChallengePeriodActive {},
+
+    #[error("Decision was already appealed")]
+    AlreadyAppealed {},
+
+    #[error("Decision was not appealed")]
+    NotAppealed {},
+
+    #[error("Appeal is still waiting for the appeal arbiter")]
+    AppealPending {},
+
+    #[error("Appeal bond must be exactly {bond}")]
+    InvalidAppealBond { bond: String },
+
+    #[error("Escrow already has a recipient")]
+    RecipientAlreadySet {},
+
+    #[error("Escrow cannot be cancelled during its lock-in period")]
+    LockedIn {},
+
+    #[error("Batch action {index} failed: {error}")]
+    BatchFailed { index: u32, error: String },
+
+    #[error("Funds sent with the batch do not cover this top-up")]
+    TopUpNotCovered {},
+
+    #[error("Remote payouts need a channel, a receiver and native tokens only")]
+    InvalidIbcPayout {},
+
+    #[error("Unexpected reply {id}")]
+    UnknownReply { id: u64 },
+
+    #[error("Transfer reply without a packet sequence")]
+    NoPacketSequence {},
 }
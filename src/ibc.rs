@@ -0,0 +1,297 @@
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{Addr, Binary, Coin, CosmosMsg, DepsMut, Env, Reply, Response, StdError, StdResult, Storage, SubMsg};
+use cw20::Balance;
+use prost::Message;
+
+use crate::contract::execute::refund_ibc_transfer;
+use crate::error::ContractError;
+use crate::msg::{IbcLifecycleComplete, SudoMsg};
+use crate::state::{Escrow, GenericBalance, IbcPayout, IbcTransfer, IBC_TRANSFERS, PENDING_IBC_TRANSFERS, RETURNED_REFUNDS};
+
+//seconds a payout has to reach the other chain before it is refunded
+pub const PACKET_LIFETIME: u64 = 60 * 60;
+
+//payouts over IBC ask for a reply, which carries the sequence of their packet
+pub const IBC_TRANSFER_REPLY_ID: u64 = 1;
+
+//ICS-20 transfer as the transfer module takes it. IbcMsg::Transfer has no memo, and without one the hooks
+//middleware doesn't report back how the transfer ended
+#[derive(Clone, PartialEq, Message)]
+pub struct MsgTransfer {
+    #[prost(string, tag = "1")]
+    pub source_port: String,
+    #[prost(string, tag = "2")]
+    pub source_channel: String,
+    #[prost(message, optional, tag = "3")]
+    pub token: Option<ProtoCoin>,
+    #[prost(string, tag = "4")]
+    pub sender: String,
+    #[prost(string, tag = "5")]
+    pub receiver: String,
+    //nanoseconds since the epoch, the timeout height is left unset
+    #[prost(uint64, tag = "7")]
+    pub timeout_timestamp: u64,
+    #[prost(string, tag = "8")]
+    pub memo: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoCoin {
+    #[prost(string, tag = "1")]
+    pub denom: String,
+    #[prost(string, tag = "2")]
+    pub amount: String,
+}
+
+//sends tokens to the other chain and has the middleware call back this contract (via "sudo") once
+//the transfer is acknowledged or timed out
+pub fn ics20_transfer(env: &Env, channel_id: &str, receiver: &str, amount: &Coin) -> CosmosMsg {
+    let msg = MsgTransfer {
+        source_port: "transfer".to_string(),
+        source_channel: channel_id.to_string(),
+        token: Some(ProtoCoin { denom: amount.denom.clone(), amount: amount.amount.to_string() }),
+        sender: env.contract.address.to_string(),
+        receiver: receiver.to_string(),
+        timeout_timestamp: env.block.time.plus_seconds(PACKET_LIFETIME).nanos(),
+        memo: format!(r#"{{"ibc_callback":"{}"}}"#, env.contract.address),
+    };
+    CosmosMsg::Stargate {
+        type_url: "/ibc.applications.transfer.v1.MsgTransfer".to_string(),
+        value: Binary::from(msg.encode_to_vec()),
+    }
+}
+
+//sends an approved escrow to its remote recipient, one transfer per denom
+pub fn transfer_out(
+    storage: &mut dyn Storage,
+    env: &Env,
+    id: &str,
+    payout: &IbcPayout,
+    escrow: &Escrow,
+    contributions: &[(Addr, GenericBalance)],
+) -> StdResult<Vec<SubMsg>> {
+    let mut msgs = vec![];
+    for coin in escrow.balance.native.iter().filter(|c| !c.amount.is_zero()) {
+        let sent = GenericBalance { native: vec![coin.clone()], cw20: vec![] };
+        let transfer = IbcTransfer {
+            id: id.to_string(),
+            payout: payout.clone(),
+            amount: coin.clone(),
+            refunds: sent.split_pro_rata(contributions, &escrow.source),
+            funder: None,
+        };
+        //replies come in the order the transfers are sent
+        PENDING_IBC_TRANSFERS.push_back(storage, &transfer)?;
+        let msg = ics20_transfer(env, &payout.channel_id, &payout.receiver, coin);
+        msgs.push(SubMsg::reply_on_success(msg, IBC_TRANSFER_REPLY_ID));
+    }
+    Ok(msgs)
+}
+
+//pays a funder that came in over IBC back on their chain. What does not arrive can be claimed again
+pub fn transfer_back(
+    storage: &mut dyn Storage,
+    env: &Env,
+    id: &str,
+    route: &IbcPayout,
+    funder: &Addr,
+    balance: &GenericBalance,
+) -> StdResult<Vec<SubMsg>> {
+    let mut msgs = vec![];
+    for coin in balance.native.iter().filter(|c| !c.amount.is_zero()) {
+        let transfer = IbcTransfer {
+            id: id.to_string(),
+            payout: route.clone(),
+            amount: coin.clone(),
+            refunds: vec![],
+            funder: Some(funder.clone()),
+        };
+        PENDING_IBC_TRANSFERS.push_back(storage, &transfer)?;
+        let msg = ics20_transfer(env, &route.channel_id, &route.receiver, coin);
+        msgs.push(SubMsg::reply_on_success(msg, IBC_TRANSFER_REPLY_ID));
+    }
+    //cw20 tokens from other funders can't leave over ICS-20
+    let cw20 = GenericBalance { native: vec![], cw20: balance.cw20.clone() };
+    if !cw20.is_empty() {
+        msgs.extend(crate::contract::send_tokens(funder, &cw20)?);
+    }
+    Ok(msgs)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    if msg.id != IBC_TRANSFER_REPLY_ID {
+        return Err(ContractError::UnknownReply { id: msg.id });
+    }
+    let transfer = PENDING_IBC_TRANSFERS
+        .pop_front(deps.storage)?
+        .ok_or(ContractError::UnknownReply { id: msg.id })?;
+    let events = msg.result.into_result().map_err(StdError::generic_err)?.events;
+    let sequence = events
+        .iter()
+        .filter(|event| event.ty == "send_packet")
+        .flat_map(|event| &event.attributes)
+        .find(|attr| attr.key == "packet_sequence")
+        .and_then(|attr| attr.value.parse::<u64>().ok())
+        .ok_or(ContractError::NoPacketSequence {})?;
+    IBC_TRANSFERS.save(deps.storage, (&transfer.payout.channel_id, sequence), &transfer)?;
+
+    let action = if transfer.funder.is_some() { "refund_sent" } else { "payout_sent" };
+    Ok(Response::new()
+        .add_attribute("action", action)
+        .add_attribute("id", transfer.id)
+        .add_attribute("sequence", sequence.to_string()))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    let SudoMsg::IbcLifecycleComplete(lifecycle) = msg;
+    let (channel, sequence, arrived) = match lifecycle {
+        IbcLifecycleComplete::IbcAck { channel, sequence, success, .. } => (channel, sequence, success),
+        IbcLifecycleComplete::IbcTimeout { channel, sequence } => (channel, sequence, false),
+    };
+    let transfer = match IBC_TRANSFERS.may_load(deps.storage, (&channel, sequence))? {
+        Some(transfer) => transfer,
+        None => return Ok(Response::new().add_attribute("action", "ibc_lifecycle")),
+    };
+    IBC_TRANSFERS.remove(deps.storage, (&channel, sequence));
+    if arrived {
+        let action = if transfer.funder.is_some() { "refund_delivered" } else { "payout_delivered" };
+        return Ok(Response::new()
+            .add_attribute("action", action)
+            .add_attribute("id", transfer.id));
+    }
+    //the transfer module returned the tokens to us
+    match transfer.funder {
+        Some(funder) => {
+            RETURNED_REFUNDS.update(deps.storage, (&transfer.id, &funder), |claimable| -> StdResult<_> {
+                let mut claimable = claimable.unwrap_or_default();
+                claimable.add_tokens(Balance::from(vec![transfer.amount]));
+                Ok(claimable)
+            })?;
+            Ok(Response::new()
+                .add_attribute("action", "refund_returned")
+                .add_attribute("id", transfer.id)
+                .add_attribute("funder", funder))
+        }
+        None => refund_ibc_transfer(deps.storage, &env, transfer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::query::{query_detail, query_settled, query_stats};
+    use crate::contract::{execute, instantiate};
+    use crate::msg::{CreateMsg, ExecuteMsg, IbcFundingMsg, InstantiateMsg};
+    use crate::state::Decision;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, BankMsg, Event, SubMsgResponse, SubMsgResult};
+
+    fn sent(sequence: u64) -> Reply {
+        let event = Event::new("send_packet").add_attribute("packet_sequence", sequence.to_string());
+        Reply { id: IBC_TRANSFER_REPLY_ID, result: SubMsgResult::Ok(SubMsgResponse { events: vec![event], data: None }) }
+    }
+
+    #[test]
+    fn fund_and_pay_out_over_ibc() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg { count: 0, minimal_donation: coin(0, "atom") };
+        instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), instantiate_msg).unwrap();
+
+        // the escrow pays out to a remote recipient, the source can't take it back
+        let remote = IbcPayout { channel_id: "channel-1".to_string(), receiver: "osmo1remote".to_string() };
+        let create = |id: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbitrate"),
+            ibc_payout: Some(remote.clone()),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("source", &coins(100, "atom")), ExecuteMsg::Create(create("out"))).unwrap();
+        let top_up = ExecuteMsg::TopUp { id: "out".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("funder", &coins(50, "atom")), top_up).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("source", &[]), ExecuteMsg::Cancel { id: "out".to_string() });
+        assert_eq!(err.unwrap_err(), crate::ContractError::RecipientAlreadySet {});
+
+        let approve = ExecuteMsg::Approve { id: "out".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbitrate", &[]), approve).unwrap();
+        let transfer = ics20_transfer(&mock_env(), "channel-1", "osmo1remote", &coin(150, "atom"));
+        assert_eq!(res.messages, vec![SubMsg::reply_on_success(transfer.clone(), IBC_TRANSFER_REPLY_ID)]);
+        // the transfer asks the hooks middleware to call back this contract
+        let CosmosMsg::Stargate { type_url, value } = transfer else { panic!("not a stargate message") };
+        assert_eq!(type_url, "/ibc.applications.transfer.v1.MsgTransfer");
+        let decoded = MsgTransfer::decode(value.as_slice()).unwrap();
+        assert_eq!(decoded.memo, format!(r#"{{"ibc_callback":"{}"}}"#, mock_env().contract.address));
+        assert_eq!(decoded.token, Some(ProtoCoin { denom: "atom".to_string(), amount: "150".to_string() }));
+        assert_eq!((decoded.source_port.as_str(), decoded.receiver.as_str()), ("transfer", "osmo1remote"));
+        reply(deps.as_mut(), mock_env(), sent(7)).unwrap();
+
+        // the payout fails on the way and the funders get back what they put in
+        let lifecycle = IbcLifecycleComplete::IbcAck {
+            channel: "channel-1".to_string(),
+            sequence: 7,
+            ack: "no such account".to_string(),
+            success: false,
+        };
+        let res = sudo(deps.as_mut(), mock_env(), SudoMsg::IbcLifecycleComplete(lifecycle)).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "funder".to_string(), amount: coins(50, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "atom") }),
+            ]
+        );
+        let settlement = query_settled(deps.as_ref(), "out".to_string()).unwrap().settlement;
+        assert_eq!(settlement.outcome, Decision::Refund);
+        assert!(settlement.parties.contains(&Addr::unchecked("funder")));
+        let stats = query_stats(deps.as_ref(), mock_env()).unwrap();
+        assert_eq!((stats.approved, stats.refunded), (0, 1));
+        assert!(IBC_TRANSFERS.is_empty(&deps.storage));
+
+        // a contributor on another chain funds an escrow through the hooks middleware
+        let fund = ExecuteMsg::FundOverIbc {
+            refund_to: IbcPayout { channel_id: "channel-1".to_string(), receiver: "osmo1funder".to_string() },
+            action: IbcFundingMsg::Create(Box::new(CreateMsg {
+                id: "in".to_string(),
+                arbiter: String::from("arbitrate"),
+                ..Default::default()
+            })),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("intermediate", &coins(60, "ibc/osmo")), fund).unwrap();
+        let details = query_detail(deps.as_ref(), mock_env(), "in".to_string()).unwrap();
+        assert_eq!((details.source.as_str(), details.native_balance), ("intermediate", coins(60, "ibc/osmo")));
+
+        // and gets the refund on their chain
+        let res = execute(deps.as_mut(), mock_env(), mock_info("intermediate", &[]), ExecuteMsg::Cancel { id: "in".to_string() }).unwrap();
+        let transfer = ics20_transfer(&mock_env(), "channel-1", "osmo1funder", &coin(60, "ibc/osmo"));
+        assert_eq!(res.messages, vec![SubMsg::reply_on_success(transfer, IBC_TRANSFER_REPLY_ID)]);
+        let res = reply(deps.as_mut(), mock_env(), sent(8)).unwrap();
+        assert_eq!(res.attributes[0].value, "refund_sent");
+
+        // a refund that times out can be claimed again, funds sent along by the middleware go back with it
+        let lifecycle = IbcLifecycleComplete::IbcTimeout { channel: "channel-1".to_string(), sequence: 8 };
+        let res = sudo(deps.as_mut(), mock_env(), SudoMsg::IbcLifecycleComplete(lifecycle)).unwrap();
+        assert!(res.messages.is_empty());
+        let claim = ExecuteMsg::ClaimRefund { id: "in".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("funder", &[]), claim.clone()).unwrap_err();
+        assert_eq!(err, crate::ContractError::NothingToClaim {});
+        let res = execute(deps.as_mut(), mock_env(), mock_info("intermediate", &coins(1, "ibc/osmo")), claim.clone()).unwrap();
+        let transfer = ics20_transfer(&mock_env(), "channel-1", "osmo1funder", &coin(61, "ibc/osmo"));
+        assert_eq!(res.messages, vec![SubMsg::reply_on_success(transfer, IBC_TRANSFER_REPLY_ID)]);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("intermediate", &[]), claim).unwrap_err();
+        assert_eq!(err, crate::ContractError::NothingToClaim {});
+
+        reply(deps.as_mut(), mock_env(), sent(9)).unwrap();
+        let lifecycle = IbcLifecycleComplete::IbcAck {
+            channel: "channel-1".to_string(),
+            sequence: 9,
+            ack: "AQ==".to_string(),
+            success: true,
+        };
+        let res = sudo(deps.as_mut(), mock_env(), SudoMsg::IbcLifecycleComplete(lifecycle)).unwrap();
+        assert_eq!(res.attributes[0].value, "refund_delivered");
+        assert!(IBC_TRANSFERS.is_empty(&deps.storage));
+        assert!(RETURNED_REFUNDS.is_empty(&deps.storage));
+    }
+}
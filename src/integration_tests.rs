@@ -68,4 +68,82 @@ mod tests {
             app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
         }
     }
+
+    mod resolver {
+        use super::*;
+        use crate::msg::{CreateMsg, ExecuteMsg, Resolution, ResolutionResponse, ResolverQueryMsg};
+        use crate::ContractError;
+        use cosmwasm_std::{
+            coins, to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
+        };
+        use cw_storage_plus::Item;
+
+        // a resolver whose answer is set by whoever runs the test
+        const RESOLUTION: Item<Resolution> = Item::new("resolution");
+
+        fn resolver_instantiate(deps: DepsMut, _env: Env, _info: MessageInfo, _msg: Empty) -> StdResult<Response> {
+            RESOLUTION.save(deps.storage, &Resolution::Pending)?;
+            Ok(Response::new())
+        }
+
+        fn resolver_execute(deps: DepsMut, _env: Env, _info: MessageInfo, msg: Resolution) -> StdResult<Response> {
+            RESOLUTION.save(deps.storage, &msg)?;
+            Ok(Response::new())
+        }
+
+        fn resolver_query(deps: Deps, _env: Env, msg: ResolverQueryMsg) -> StdResult<Binary> {
+            match msg {
+                ResolverQueryMsg::Resolution { .. } => {
+                    to_binary(&ResolutionResponse { resolution: RESOLUTION.load(deps.storage)? })
+                }
+            }
+        }
+
+        fn contract_resolver() -> Box<dyn Contract<Empty>> {
+            let contract = ContractWrapper::new(resolver_execute, resolver_instantiate, resolver_query);
+            Box::new(contract)
+        }
+
+        #[test]
+        fn resolve_by_contract() {
+            let (mut app, otms) = proper_instantiate();
+            app.init_modules(|router, _, storage| {
+                router.bank.init_balance(storage, &Addr::unchecked("source"), coins(300, NATIVE_DENOM))
+            })
+            .unwrap();
+            let resolver_id = app.store_code(contract_resolver());
+            let resolver = app
+                .instantiate_contract(resolver_id, Addr::unchecked(ADMIN), &Empty {}, &[], "resolver", None)
+                .unwrap();
+
+            for id in ["paid", "returned"] {
+                let create = CreateMsg {
+                    id: id.to_string(),
+                    arbiter: resolver.to_string(),
+                    recipient: Some("recd".to_string()),
+                    resolver: true,
+                    ..Default::default()
+                };
+                app.execute_contract(
+                    Addr::unchecked("source"),
+                    otms.addr(),
+                    &ExecuteMsg::Create(create),
+                    &coins(100, NATIVE_DENOM),
+                )
+                .unwrap();
+            }
+
+            let resolve = |id: &str| ExecuteMsg::Resolve { id: id.to_string() };
+            let err = app.execute_contract(Addr::unchecked(USER), otms.addr(), &resolve("paid"), &[]).unwrap_err();
+            assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::ResolutionPending {});
+
+            app.execute_contract(Addr::unchecked(ADMIN), resolver.clone(), &Resolution::Approve, &[]).unwrap();
+            app.execute_contract(Addr::unchecked(USER), otms.addr(), &resolve("paid"), &[]).unwrap();
+            assert_eq!(app.wrap().query_balance("recd", NATIVE_DENOM).unwrap().amount, Uint128::new(100));
+
+            app.execute_contract(Addr::unchecked(ADMIN), resolver, &Resolution::Refund, &[]).unwrap();
+            app.execute_contract(Addr::unchecked(USER), otms.addr(), &resolve("returned"), &[]).unwrap();
+            assert_eq!(app.wrap().query_balance("source", NATIVE_DENOM).unwrap().amount, Uint128::new(200));
+        }
+    }
 }
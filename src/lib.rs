@@ -1,6 +1,7 @@
 pub mod contract;
 mod error;
 pub mod helpers;
+pub mod ibc;
 pub mod integration_tests;
 pub mod msg;
 pub mod state;
@@ -1,6 +1,9 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Coin, Api, Addr, StdResult};
+use cosmwasm_std::{Coin, Api, Addr, Binary, StdResult, Uint128};
 use cw20::{Cw20Coin, Cw20ReceiveMsg};
+use cw_utils::{Duration, Expiration};
+
+use crate::state::{Amendment, ArbiterProfile, ArbiterStats, Consent, DurationLimits, ExpiryMode, IbcPayout, PendingDecision, RecipientShare, Settlement, StakingConfig, Submission, SubmissionLimits, Unbonding};
 
 #[cw_serde]
 pub struct InstantiateMsg {
@@ -8,6 +11,9 @@ pub struct InstantiateMsg {
     pub minimal_donation: Coin,
 }
 
+#[cw_serde]
+pub struct MigrateMsg {}
+
 #[cw_serde]
 pub enum ExecuteMsg {
     Increment {},
@@ -27,11 +33,68 @@ pub enum ExecuteMsg {
         id: String,
     },
 
-    //Approve sends all tokens to the recipient. Only the arbiter can do this
+    //Cancel returns the balance to the funders while no recipient is set, the arbiter has not
+    //decided and no bounty submission is open, after the lock-in period if any. Only the source can do this
+    Cancel{
+        id: String,
+    },
+
+    //CreateFromTemplate creates an escrow from the defaults of a template and the given overrides.
+    //With a version, it fails if the template was updated since
+    CreateFromTemplate{
+        template: String,
+        id: String,
+        version: Option<u64>,
+        overrides: TemplateOverrides,
+    },
+
+    //RegisterTemplate stores escrow defaults under a name, or updates them as a new version.
+    //Only the template owner or the contract owner can update a template
+    RegisterTemplate{
+        name: String,
+        defaults: CreateMsg,
+    },
+
+    //RemoveTemplate deletes a template. Only the template owner or the contract owner can do this
+    RemoveTemplate{
+        name: String,
+    },
+
+    //Batch runs several escrow actions in one transaction, each authorized as if sent on its own.
+    //Top-ups are paid from the sent funds, whatever is not allocated is returned
+    Batch{
+        actions: Vec<EscrowAction>,
+        mode: BatchMode,
+    },
+
+    //FundOverIbc is the memo call of an ICS-20 transfer through the hooks middleware, which delivers
+    //the vouchers as funds. What the funder gets back goes to refund_to on their chain
+    FundOverIbc{
+        refund_to: IbcPayout,
+        action: IbcFundingMsg,
+    },
+
+    //ClaimRefund sends a refund that did not reach the funder's chain again, over the latest route
+    //the funder came in on. Funds sent along, as the hooks middleware needs, go back with it
+    ClaimRefund{
+        id: String,
+    },
+
+    //Approve sends all tokens to the recipient. Only the arbiter can do this.
+    //With a challenge period, the decision is pending until it ends or an appeal is decided
     Approve{
         id: String,
     },
 
+    //ApproveWithSignature approves with the arbiter's signature over the JSON encoding of a
+    //SignedApproval, secp256k1 (compressed key, sha256 prehash) or ed25519. Anyone can relay it
+    ApproveWithSignature{
+        id: String,
+        signature: Binary,
+        pubkey: Binary,
+        nonce: u64,
+    },
+
     //Refund returns all remaining tokens to the original sender,
     //arbiter can do this anytime or anyone can do this after a timeout
     Refund{
@@ -40,6 +103,224 @@ pub enum ExecuteMsg {
 
     //This accepts a properly-encoded ReceiveMsg from a cw20 contract
     Receive(Cw20ReceiveMsg),
+
+    //Disburse releases all stream payments that became due since the last disbursement,
+    //limited to the remaining balance. Anyone can do this
+    Disburse{
+        id: String,
+    },
+
+    //CancelStream pays out what is due and refunds the remainder, subject to the challenge period.
+    //Only the arbiter can do this
+    CancelStream{
+        id: String,
+    },
+
+    //Amend proposes new values for the given fields, fields left out stay unchanged.
+    //Changes need both the source and the arbiter: one proposes, the other accepts.
+    //The source can extend its own deadlines without the arbiter.
+    Amend{
+        id: String,
+        title: Option<String>,
+        description: Option<String>,
+        expires: Option<Vec<Expiration>>,
+        cw20_whitelist: Option<Vec<String>>,
+    },
+
+    //AcceptAmendment applies the pending amendment. Only the party that did not propose it can do this
+    AcceptAmendment{
+        id: String,
+    },
+
+    //RejectAmendment drops the pending amendment. The source or the arbiter can do this
+    RejectAmendment{
+        id: String,
+    },
+
+    //set the recipients the balance is split between on approval, replacing the recipient.
+    //Only the arbiter can do this
+    SetRecipients{
+        id: String,
+        recipients: Vec<RecipientShareMsg>,
+        //receives what is left over from rounding, the first recipient by default
+        dust_recipient: Option<String>,
+    },
+
+    //AcceptRole confirms the recipient can receive the funds. Only the recipient can do this
+    AcceptRole{
+        id: String,
+    },
+
+    //SubmitClaim enters a solution for a bounty that has no recipient yet.
+    //The hunter receives the bounty if it wins, the sender by default
+    SubmitClaim{
+        id: String,
+        link: String,
+        hunter: Option<String>,
+    },
+
+    //CommitClaim enters a sealed submission for a sealed bounty: the sha256 hash of a SealedClaim
+    CommitClaim{
+        id: String,
+        commitment: Binary,
+    },
+
+    //RevealClaim opens a commitment, which is checked against the hash. Only the committer can do this
+    RevealClaim{
+        id: String,
+        submission_id: u64,
+        link: String,
+        hunter: Option<String>,
+        salt: String,
+    },
+
+    //Release approves a hash-locked escrow by presenting the preimage of its hashlock.
+    //Anyone can do this before the deadline, the preimage is emitted as an attribute
+    Release{
+        id: String,
+        preimage: Binary,
+    },
+
+    //FillSwap deposits the ask of a swap escrow, which settles both sides at once.
+    //Only the recipient can do this, anyone if no recipient is set
+    FillSwap{
+        id: String,
+    },
+
+    //Resolve asks the resolver contract of the escrow for the outcome and approves or refunds accordingly.
+    //Anyone can do this
+    Resolve{
+        id: String,
+    },
+
+    //TakeOverArbitration makes the backup arbiter the arbiter once the arbiter is inactive.
+    //Only the backup arbiter can do this
+    TakeOverArbitration{
+        id: String,
+    },
+
+    //NominateArbiter proposes a new arbiter once the arbiter is inactive, which is appointed when the
+    //source and the recipient nominated the same one. Only the source and the recipient can do this
+    NominateArbiter{
+        id: String,
+        arbiter: String,
+    },
+
+    //Appeal escalates a pending decision to the appeal arbiter, with the appeal bond attached.
+    //Only the source and the recipients can do this, during the challenge period
+    Appeal{
+        id: String,
+    },
+
+    //DecideAppeal approves or refunds an appealed escrow. Only the appeal arbiter can do this
+    DecideAppeal{
+        id: String,
+        approve: bool,
+    },
+
+    //FinalizeDecision carries out a pending decision once the challenge period is over, or once an appeal
+    //went undecided for another challenge period. Anyone can do this
+    FinalizeDecision{
+        id: String,
+    },
+
+    //MutualApprove consents to approval without the arbiter, which happens once the source and
+    //all recipients consented. Only they can do this
+    MutualApprove{
+        id: String,
+    },
+
+    //MutualRefund consents to a refund without the arbiter, which happens once the source and
+    //all recipients consented. Only they can do this
+    MutualRefund{
+        id: String,
+    },
+
+    //PickWinner makes the hunter of the submission the recipient and approves the escrow.
+    //Only the arbiter can do this
+    PickWinner{
+        id: String,
+        submission_id: u64,
+    },
+
+    //RejectSubmission turns down an open submission. Only the arbiter can do this
+    RejectSubmission{
+        id: String,
+        submission_id: u64,
+    },
+
+    //SetSubmissionLimits rate-limits bounty submissions per address. Only the owner can do this
+    SetSubmissionLimits{
+        cooldown_blocks: u64,
+        max_per_bounty: Option<u32>,
+    },
+
+    //RegisterArbiter creates or updates the profile of the sender in the arbiter registry
+    RegisterArbiter{
+        name: String,
+        contact_hash: Binary,
+        fee_terms: String,
+    },
+
+    //UnregisterArbiter removes the sender from the arbiter registry, its stats are kept
+    UnregisterArbiter{},
+
+    //SetArbiterPolicy decides whether new escrows need a registered arbiter. Only the owner can do this
+    SetArbiterPolicy{
+        require_registered: bool,
+    },
+
+    //Stake bonds the sent staking denom for the sender. Only registered arbiters can do this
+    Stake{},
+
+    //Unbond starts the unbonding period for part of the sender's stake
+    Unbond{
+        amount: Uint128,
+    },
+
+    //ClaimUnbonded pays out unbonded stake whose unbonding period is over
+    ClaimUnbonded{},
+
+    //Slash takes stake from an arbiter, bonded first, and sends it to the appellant. Only for
+    //escrows where an appeal against the arbiter was upheld, up to the stake the escrow locked.
+    //Only the owner or the governance address can do this
+    Slash{
+        arbiter: String,
+        id: String,
+        amount: Uint128,
+    },
+
+    //SetStakingConfig sets the staking denom, minimum stake and unbonding period. Only the owner can do this
+    SetStakingConfig{
+        denom: String,
+        min_stake_bps: u64,
+        unbonding_period: Duration,
+        governance: Option<String>,
+    },
+
+    //SweepExpired refunds up to `limit` expired escrows, oldest deadline first. Anyone can call this
+    //and earns the keeper tip for each escrow swept while the reserve lasts. Sweeping nothing is not an
+    //error, the deadlines looked at on the way are still cleaned up
+    SweepExpired{
+        limit: u32,
+    },
+
+    //FundKeeperReserve adds the sent funds to the reserve keeper tips are paid from
+    FundKeeperReserve{},
+
+    //SetKeeperTip sets what sweeping one escrow pays at most, none to stop paying. Only the owner can do this
+    SetKeeperTip{
+        tip: Option<Coin>,
+    },
+
+    //SetDurationLimits bounds the duration of new escrows, unset values are not checked.
+    //Only the owner can do this
+    SetDurationLimits{
+        min_blocks: Option<u64>,
+        max_blocks: Option<u64>,
+        min_seconds: Option<u64>,
+        max_seconds: Option<u64>,
+    },
 }
 
 #[cw_serde]
@@ -54,6 +335,115 @@ pub enum QueryMsg {
 
     #[returns(DetailsResponse)]
     Details{id: String},
+
+    //pending and applied amendments of an escrow, the history outlives the settlement
+    #[returns(AmendmentsResponse)]
+    Amendments{id: String},
+
+    #[returns(DurationLimits)]
+    DurationLimits{},
+
+    //everyone who funded the escrow and what they deposited
+    #[returns(ContributorsResponse)]
+    Contributors{id: String},
+
+    //submissions to a bounty, oldest first
+    #[returns(SubmissionsResponse)]
+    Submissions{
+        id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    #[returns(SubmissionLimits)]
+    SubmissionLimits{},
+
+    //profile and stats of an arbiter, registered or not
+    #[returns(ArbiterResponse)]
+    Arbiter{address: String},
+
+    //registered arbiters by address
+    #[returns(ArbitersResponse)]
+    Arbiters{
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    //registered arbiters with the most decisions first, faster ones first among equals
+    #[returns(ArbitersResponse)]
+    RankedArbiters{
+        limit: Option<u32>,
+    },
+
+    //bonded and unbonding stake of an arbiter
+    #[returns(StakeResponse)]
+    Stake{address: String},
+
+    #[returns(StakingConfig)]
+    StakingConfig{},
+
+    #[returns(TemplateResponse)]
+    Template{name: String},
+
+    //templates by name
+    #[returns(TemplatesResponse)]
+    Templates{
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    //the nonce the address must sign its next approval with
+    #[returns(NonceResponse)]
+    Nonce{address: String},
+
+    //totals over all escrows
+    #[returns(StatsResponse)]
+    Stats{},
+
+    //the keeper tip and what is left to pay it from
+    #[returns(KeeperResponse)]
+    Keeper{},
+
+    //how an escrow was settled, once it is no longer in Details
+    #[returns(SettledResponse)]
+    SettledDetails{id: String},
+
+    //settled escrows the address was a party or the arbiter of, by id
+    #[returns(SettledHistoryResponse)]
+    SettledHistory{
+        party: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+}
+
+#[cw_serde]
+pub enum EscrowAction {
+    Approve { id: String },
+    Refund { id: String },
+    //allocates part of the sent native funds to the escrow
+    TopUp { id: String, amount: Vec<Coin> },
+    SetRecipient { id: String, recipient: String },
+}
+
+#[cw_serde]
+pub enum BatchMode {
+    //any failing action fails the whole batch
+    AllOrNothing,
+    //failing actions are skipped and reported in the response data
+    BestEffort,
+}
+
+//Response data of a batch, one result per action
+#[cw_serde]
+pub struct BatchResponse {
+    pub results: Vec<BatchResult>,
+}
+
+#[cw_serde]
+pub struct BatchResult {
+    pub id: String,
+    pub error: Option<String>,
 }
 
 // We define a custom struct for each query response
@@ -79,21 +469,166 @@ pub struct DetailsResponse{
     pub source: String,
     pub title: String,
     pub description: String,
-    //when end height set and block height exceeds this value the escrow is expired.
+    //deadlines of the escrow, combined according to expiry_mode.
     //Once a escrow is expired, it can be returned to the original funder(via "refund").
-    pub end_height: Option<u64>,
-    //block time exceeds this value the escrow is expired. 
-    //once an escrow is expired, it can be returned to the original funder(via "refund").
-    pub end_time: Option<u64>,
+    pub expires: Vec<Expiration>,
+    pub expiry_mode: ExpiryMode,
+    //time left until each of the deadlines, zero once passed
+    pub expires_in: Vec<Duration>,
     //Balance in native tokens
     pub native_balance: Vec<Coin>,
     //Balance in cw20 tokens
     pub cw20_balance: Vec<Cw20Coin>,
     //whitelisted cw20 tokens
     pub cw20_whitelist: Vec<String>,
+    //recurring payments out of the balance, if any
+    pub stream: Option<StreamDetails>,
+    //whether the recipient must accept its role before approval
+    pub require_acceptance: bool,
+    pub recipient_accepted: bool,
+    //if approved the funds are split between these, replacing the recipient
+    pub recipients: Vec<RecipientShare>,
+    pub dust_recipient: Option<String>,
+    //whether the escrow takes bounty submissions
+    pub bounty: bool,
+    //whether bounty submissions are sealed, and when the reveal phase starts
+    pub sealed: bool,
+    pub reveal_start: Option<Expiration>,
+    //SHA-256 hash that releases the escrow to the recipient, if any
+    pub hashlock: Option<Binary>,
+    //what the counterparty deposits to settle a swap, if any
+    pub ask: Option<SwapAsk>,
+    //whether the arbiter is a resolver contract
+    pub resolver: bool,
+    //block height of the latest arbiter action, and the blocks of inaction after which it can be replaced
+    pub last_arbiter_action: u64,
+    pub inactivity_window: Option<u64>,
+    pub backup_arbiter: Option<String>,
+    //blocks a decision can be appealed, who decides appeals and what appealing costs
+    pub challenge_period: Option<u64>,
+    pub appeal_arbiter: Option<String>,
+    pub appeal_bond: Option<Coin>,
+    pub pending_decision: Option<PendingDecision>,
+    //consents towards a mutual approval or refund
+    pub consents: Vec<Consent>,
+    //the source cannot cancel before this point
+    pub lock_in_until: Option<Expiration>,
+    pub ibc_payout: Option<IbcPayout>,
+}
+
+#[cw_serde]
+pub struct AmendmentsResponse {
+    pub pending: Option<Amendment>,
+    //oldest first
+    pub history: Vec<Amendment>,
+}
+
+#[cw_serde]
+pub struct ArbiterResponse {
+    pub address: String,
+    pub profile: Option<ArbiterProfile>,
+    pub stats: ArbiterStats,
+    //average seconds from creation of an escrow to the decision
+    pub average_resolution_time: Option<u64>,
+}
+
+#[cw_serde]
+pub struct ArbitersResponse {
+    pub arbiters: Vec<ArbiterResponse>,
+}
+
+#[cw_serde]
+pub struct StakeResponse {
+    pub bonded: Uint128,
+    pub unbonding: Vec<Unbonding>,
+}
+
+#[cw_serde]
+pub struct TemplateResponse {
+    pub name: String,
+    pub owner: String,
+    pub version: u64,
+    pub defaults: CreateMsg,
+}
+
+#[cw_serde]
+pub struct TemplatesResponse {
+    pub templates: Vec<TemplateResponse>,
+}
+
+#[cw_serde]
+pub struct StatsResponse {
+    //escrows currently holding funds, of which expired_unclaimed passed their deadline. The latter is
+    //counted from the oldest passed deadlines and stops at 100 of them
+    pub open: u64,
+    pub expired_unclaimed: u64,
+    //escrows created, approved and refunded so far
+    pub created: u64,
+    pub approved: u64,
+    pub refunded: u64,
+    //tokens held by open escrows
+    pub locked_native: Vec<Coin>,
+    pub locked_cw20: Vec<Cw20Coin>,
+    pub donated: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct SettledResponse {
+    pub id: String,
+    pub settlement: Settlement,
+}
+
+#[cw_serde]
+pub struct SettledHistoryResponse {
+    pub escrows: Vec<SettledResponse>,
+}
+
+#[cw_serde]
+pub struct KeeperResponse {
+    pub tip: Option<Coin>,
+    pub reserve: Vec<Coin>,
+}
+
+//Response data of a sweep
+#[cw_serde]
+pub struct SweepResponse {
+    pub swept: Vec<String>,
 }
 
 #[cw_serde]
+pub struct NonceResponse {
+    pub nonce: u64,
+}
+
+#[cw_serde]
+pub struct SubmissionsResponse {
+    pub submissions: Vec<Submission>,
+}
+
+#[cw_serde]
+pub struct ContributorsResponse {
+    pub contributors: Vec<Contribution>,
+}
+
+#[cw_serde]
+pub struct Contribution {
+    pub address: String,
+    pub native: Vec<Coin>,
+    pub cw20: Vec<Cw20Coin>,
+}
+
+#[cw_serde]
+pub struct StreamDetails {
+    pub native: Vec<Coin>,
+    pub cw20: Vec<Cw20Coin>,
+    pub period: Duration,
+    pub recipient: String,
+    //block height or time (in seconds) up to which payments were released
+    pub last_disbursed: u64,
+}
+
+#[cw_serde]
+#[derive(Default)]
 pub struct CreateMsg {
     //id is a human-readable name for the escrow to use later
     pub id: String,
@@ -110,19 +645,165 @@ pub struct CreateMsg {
     //Longer description of the escrow, e.g what conditions should be met 
     pub description: String,
 
-    //When end height set and block height exceeds this value, the escrow is expired
-    //Once an escrow is expired, it can be returned to the original funder(via "refund").
-    pub end_height: Option<u64>,
-
-    //When end time (in seconds since epoch 00:00:00 UTC on 1 January 1970) is set and 
-    //block time exceeds this value, the escrow is expired.
+    //Deadlines at a block height and/or a block time, at most one of each kind.
     //Once an escrow is expired, it can be returned to the original funder (via "refund").
-    pub end_time: Option<u64>,
+    pub expires: Option<Vec<Expiration>>,
+
+    //Relative alternative to expires: deadlines this many blocks or seconds after creation
+    pub expires_in: Option<Vec<Duration>>,
+
+    //Whether the escrow expires when any (default) or when all of its deadlines passed
+    pub expiry_mode: Option<ExpiryMode>,
 
     //Besides any possible tokens sent with the createMsg, this is a list of all cw20 token addresses
     //that are accepted by the escrow during a top-up.This is required to avoid a DoS attack by topping-up
     //with an invalid cw20 contract.
     pub cw20_whitelist: Option<Vec<String>>,
+
+    //When set, the balance is paid out to the stream recipient in equal amounts every period
+    pub stream: Option<StreamMsg>,
+
+    //When true, approval waits until the recipient accepted its role (via "accept_role")
+    #[serde(default)]
+    pub require_acceptance: bool,
+
+    //Alternative to recipient: if approved, funds are split between these by share
+    pub recipients: Option<Vec<RecipientShareMsg>>,
+
+    //Receives what is left over from rounding the split, the first recipient by default
+    pub dust_recipient: Option<String>,
+
+    //When true, hunters can submit claims (via "submit_claim") until the arbiter picks a winner
+    #[serde(default)]
+    pub bounty: bool,
+
+    //When true, bounty submissions are committed (via "commit_claim") and revealed later
+    #[serde(default)]
+    pub sealed: bool,
+
+    //Commitments are taken before and revealed after this point, by default reveals can follow any later block
+    pub reveal_start: Option<Expiration>,
+
+    //SHA-256 hash of a secret: whoever presents the secret (via "release") approves the escrow
+    //without the arbiter. Needs a deadline, after which anyone can refund the source
+    pub hashlock: Option<Binary>,
+
+    //Makes this a swap: the recipient (or anyone, if none is set) deposits the ask (via "fill_swap")
+    //and receives the balance, the source receives the ask. Needs a deadline, after which anyone can
+    //refund the source
+    pub ask: Option<SwapAsk>,
+
+    //When true, the arbiter is a contract implementing ResolverQueryMsg, whose answer anyone can act on
+    //(via "resolve"). Anyone can refund once expired
+    #[serde(default)]
+    pub resolver: bool,
+
+    //Blocks without arbiter action after which the source and the recipient can jointly appoint
+    //a new arbiter (via "nominate_arbiter"), or the backup arbiter takes over
+    pub inactivity_window: Option<u64>,
+
+    //Takes over (via "take_over_arbitration") once the arbiter is inactive
+    pub backup_arbiter: Option<String>,
+
+    //Blocks an arbiter decision stays pending before it is carried out (via "finalize_decision").
+    //Meanwhile the source or a recipient can appeal it to the appeal arbiter
+    pub challenge_period: Option<u64>,
+    pub appeal_arbiter: Option<String>,
+
+    //What an appeal costs, returned if the appeal is upheld and paid to the arbiter otherwise
+    pub appeal_bond: Option<Coin>,
+
+    //Blocks or seconds after creation during which the source cannot cancel (via "cancel")
+    pub lock_in: Option<Duration>,
+
+    //Pays the escrow out over IBC instead of to a local recipient. Native tokens only
+    pub ibc_payout: Option<IbcPayout>,
+}
+
+//What an incoming ICS-20 transfer does with the tokens, see "fund_over_ibc"
+#[cw_serde]
+pub enum IbcFundingMsg {
+    Create(Box<CreateMsg>),
+    TopUp { id: String },
+}
+
+//Called by the chain, not by accounts
+#[cw_serde]
+pub enum SudoMsg {
+    //The hooks middleware reports how a transfer sent by this contract ended
+    IbcLifecycleComplete(IbcLifecycleComplete),
+}
+
+#[cw_serde]
+pub enum IbcLifecycleComplete {
+    IbcAck {
+        channel: String,
+        sequence: u64,
+        ack: String,
+        success: bool,
+    },
+    IbcTimeout {
+        channel: String,
+        sequence: u64,
+    },
+}
+
+//What differs between escrows created from the same template. Setting either deadline field replaces
+//both deadline fields of the template, and setting either recipient field replaces both recipient fields
+#[cw_serde]
+#[derive(Default)]
+pub struct TemplateOverrides {
+    pub recipient: Option<String>,
+    pub recipients: Option<Vec<RecipientShareMsg>>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub expires: Option<Vec<Expiration>>,
+    pub expires_in: Option<Vec<Duration>>,
+}
+
+//The other side of a swap, native coins or a single cw20 token
+#[cw_serde]
+pub struct SwapAsk {
+    pub native: Vec<Coin>,
+    pub cw20: Vec<Cw20Coin>,
+}
+
+//The preimage of a sealed submission, commitments are the sha256 hash of its JSON encoding
+#[cw_serde]
+pub struct SealedClaim {
+    pub id: String,
+    pub link: String,
+    pub hunter: String,
+    pub salt: String,
+}
+
+//What an arbiter signs to approve off-chain. The chain and contract keep it from being replayed elsewhere
+#[cw_serde]
+pub struct SignedApproval {
+    pub contract_address: String,
+    pub chain_id: String,
+    pub id: String,
+    pub action: String,
+    pub nonce: u64,
+}
+
+#[cw_serde]
+pub struct RecipientShareMsg {
+    pub address: String,
+    //in basis points, the shares of an escrow must sum up to 10000
+    pub share: u64,
+}
+
+#[cw_serde]
+pub struct StreamMsg {
+    //paid out every period in native tokens
+    pub native: Vec<Coin>,
+    //paid out every period in cw20 tokens
+    pub cw20: Vec<Cw20Coin>,
+    //length of one period, in blocks or in seconds
+    pub period: Duration,
+    //receives the stream payments
+    pub recipient: String,
 }
 
 impl CreateMsg {
@@ -134,13 +815,45 @@ impl CreateMsg {
     }
 }
 
+//The query interface of resolver contracts
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum ResolverQueryMsg {
+    //the outcome of the escrow with the given id
+    #[returns(ResolutionResponse)]
+    Resolution { id: String },
+}
+
+#[cw_serde]
+pub struct ResolutionResponse {
+    pub resolution: Resolution,
+}
+
+#[cw_serde]
+pub enum Resolution {
+    Approve,
+    Refund,
+    Pending,
+}
+
 #[cw_serde]
 pub enum ReceiveMsg {
-    Create(CreateMsg),
+    Create(Box<CreateMsg>),
     /// Adds all sent native tokens to the contract
     TopUp {
         id: String,
     },
+    /// Fills a swap escrow with the sent cw20 tokens
+    FillSwap {
+        id: String,
+    },
+    /// Creates an escrow from a template with the sent cw20 tokens
+    CreateFromTemplate {
+        template: String,
+        id: String,
+        version: Option<u64>,
+        overrides: TemplateOverrides,
+    },
 }
 
 
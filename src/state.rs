@@ -1,11 +1,14 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use cosmwasm_schema::cw_serde;
 
 use cw20::{Balance, Cw20CoinVerified};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Coin, Env, Timestamp, StdResult, Order, Storage};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Binary, Coin, Env, Timestamp, StdError, StdResult, Order, Record, Storage, Uint128};
+use cw_storage_plus::{Deque, Item, Map};
+use cw_utils::{Duration, Expiration};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct State {
@@ -16,6 +19,27 @@ pub struct State {
 pub const STATE: Item<State> = Item::new("state");
 pub const MINIMAL_DONATION: Item<Coin> = Item::new("minimal_donation");
 
+//Bounds on how long a new escrow may run, set by the owner
+#[cw_serde]
+#[derive(Default)]
+pub struct DurationLimits {
+    pub min_blocks: Option<u64>,
+    pub max_blocks: Option<u64>,
+    pub min_seconds: Option<u64>,
+    pub max_seconds: Option<u64>,
+}
+
+impl DurationLimits {
+    pub fn has_max(&self) -> bool {
+        self.max_blocks.is_some() || self.max_seconds.is_some()
+    }
+}
+
+//next nonce expected in an arbiter's signed approval
+pub const NONCES: Map<&Addr, u64> = Map::new("nonces");
+
+pub const DURATION_LIMITS: Item<DurationLimits> = Item::new("duration_limits");
+
 #[cw_serde]
 #[derive(Default)]
 pub struct GenericBalance {
@@ -56,6 +80,202 @@ impl GenericBalance {
            }
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.native.iter().all(|c| c.amount.is_zero()) && self.cw20.iter().all(|c| c.amount.is_zero())
+    }
+
+    //returns this balance multiplied by the given factor
+    pub fn times(&self, factor: u64) -> StdResult<GenericBalance> {
+        let factor = Uint128::from(factor);
+        let native = self
+            .native
+            .iter()
+            .map(|c| Ok(Coin { denom: c.denom.clone(), amount: c.amount.checked_mul(factor)? }))
+            .collect::<StdResult<Vec<_>>>()?;
+        let cw20 = self
+            .cw20
+            .iter()
+            .map(|c| Ok(Cw20CoinVerified { address: c.address.clone(), amount: c.amount.checked_mul(factor)? }))
+            .collect::<StdResult<Vec<_>>>()?;
+        Ok(GenericBalance { native, cw20 })
+    }
+
+    //whether both balances hold the same amount of every token
+    pub fn matches(&self, other: &GenericBalance) -> bool {
+        let mut rest = self.clone();
+        let taken = rest.take(other);
+        let mut missing = other.clone();
+        missing.take(&taken);
+        rest.is_empty() && missing.is_empty()
+    }
+
+    //removes up to `want` from this balance and returns what was actually taken
+    pub fn take(&mut self, want: &GenericBalance) -> GenericBalance {
+        let mut taken = GenericBalance::default();
+        for coin in &want.native {
+            if let Some(exist) = self.native.iter_mut().find(|c| c.denom == coin.denom) {
+                let amount = std::cmp::min(exist.amount, coin.amount);
+                if !amount.is_zero() {
+                    exist.amount -= amount;
+                    taken.native.push(Coin { denom: coin.denom.clone(), amount });
+                }
+            }
+        }
+        for token in &want.cw20 {
+            if let Some(exist) = self.cw20.iter_mut().find(|c| c.address == token.address) {
+                let amount = std::cmp::min(exist.amount, token.amount);
+                if !amount.is_zero() {
+                    exist.amount -= amount;
+                    taken.cw20.push(Cw20CoinVerified { address: token.address.clone(), amount });
+                }
+            }
+        }
+        self.native.retain(|c| !c.amount.is_zero());
+        self.cw20.retain(|c| !c.amount.is_zero());
+        taken
+    }
+
+    //divides the balance by shares, rounding every part down. What is left over from the
+    //rounding goes to the dust recipient, which does not need to hold a share itself
+    pub fn split(&self, shares: &[RecipientShare], dust_recipient: &Addr) -> Vec<(Addr, GenericBalance)> {
+        let weights: Vec<Uint128> = shares.iter().map(|s| Uint128::from(s.share)).collect();
+        self.split_weighted(
+            shares.iter().map(|s| s.address.clone()).collect(),
+            dust_recipient,
+            |_| weights.clone(),
+            |_| weights.clone(),
+        )
+    }
+
+    //divides the balance between contributors in proportion to what each deposited of every token,
+    //rounding down. Leftovers and tokens nobody is on record for go to the dust recipient
+    pub fn split_pro_rata(
+        &self,
+        contributions: &[(Addr, GenericBalance)],
+        dust_recipient: &Addr,
+    ) -> Vec<(Addr, GenericBalance)> {
+        self.split_weighted(
+            contributions.iter().map(|(addr, _)| addr.clone()).collect(),
+            dust_recipient,
+            |denom| {
+                contributions
+                    .iter()
+                    .map(|(_, c)| c.native.iter().find(|d| d.denom == denom).map_or(Uint128::zero(), |d| d.amount))
+                    .collect()
+            },
+            |address| {
+                contributions
+                    .iter()
+                    .map(|(_, c)| c.cw20.iter().find(|d| d.address == *address).map_or(Uint128::zero(), |d| d.amount))
+                    .collect()
+            },
+        )
+    }
+
+    //divides every token by the weights given for it, one weight per address
+    fn split_weighted(
+        &self,
+        addresses: Vec<Addr>,
+        dust_recipient: &Addr,
+        native_weights: impl Fn(&str) -> Vec<Uint128>,
+        cw20_weights: impl Fn(&Addr) -> Vec<Uint128>,
+    ) -> Vec<(Addr, GenericBalance)> {
+        let mut parts: Vec<(Addr, GenericBalance)> =
+            addresses.into_iter().map(|addr| (addr, GenericBalance::default())).collect();
+        let dust_index = match parts.iter().position(|(addr, _)| addr == dust_recipient) {
+            Some(idx) => idx,
+            None => {
+                parts.push((dust_recipient.clone(), GenericBalance::default()));
+                parts.len() - 1
+            }
+        };
+
+        for coin in &self.native {
+            let (amounts, rest) = weighted_parts(coin.amount, &native_weights(&coin.denom));
+            let amounts = amounts.into_iter().enumerate().chain([(dust_index, rest)]);
+            for (i, amount) in amounts.filter(|(_, a)| !a.is_zero()) {
+                parts[i].1.add_tokens(Balance::from(vec![Coin { denom: coin.denom.clone(), amount }]));
+            }
+        }
+        for token in &self.cw20 {
+            let (amounts, rest) = weighted_parts(token.amount, &cw20_weights(&token.address));
+            let amounts = amounts.into_iter().enumerate().chain([(dust_index, rest)]);
+            for (i, amount) in amounts.filter(|(_, a)| !a.is_zero()) {
+                parts[i].1.add_tokens(Balance::Cw20(Cw20CoinVerified { address: token.address.clone(), amount }));
+            }
+        }
+
+        parts.into_iter().filter(|(_, part)| !part.is_empty()).collect()
+    }
+}
+
+//divides an amount by weights, rounding every part down, and returns the parts with the rest.
+//Without any weight the whole amount is left over
+fn weighted_parts(amount: Uint128, weights: &[Uint128]) -> (Vec<Uint128>, Uint128) {
+    let total: Uint128 = weights.iter().sum();
+    if total.is_zero() {
+        return (vec![], amount);
+    }
+    let mut rest = amount;
+    let parts = weights
+        .iter()
+        .map(|weight| {
+            let part = amount.multiply_ratio(*weight, total);
+            rest -= part;
+            part
+        })
+        .collect();
+    (parts, rest)
+}
+
+pub const TOTAL_SHARES: u64 = 10_000;
+
+//A recipient's part of the balance when it is split on approval
+#[cw_serde]
+pub struct RecipientShare {
+    pub address: Addr,
+
+    //in basis points, the shares of an escrow sum up to TOTAL_SHARES
+    pub share: u64,
+
+    //whether this recipient accepted its role
+    #[serde(default)]
+    pub accepted: bool,
+}
+
+#[cw_serde]
+pub struct Stream {
+    //paid out to the recipient once every period
+    pub amount: GenericBalance,
+
+    //length of one period, in blocks or in seconds
+    pub period: Duration,
+
+    //receives the stream payments
+    pub recipient: Addr,
+
+    //block height or time (in seconds, matching the period) up to which payments were released
+    pub last_disbursed: u64,
+}
+
+impl Stream {
+    //number of whole periods that passed since the last disbursement
+    pub fn periods_due(&self, env: &Env) -> u64 {
+        let (now, length) = match self.period {
+            Duration::Height(h) => (env.block.height, h),
+            Duration::Time(t) => (env.block.time.seconds(), t),
+        };
+        now.saturating_sub(self.last_disbursed) / length
+    }
+
+    pub fn advance(&mut self, periods: u64) {
+        let length = match self.period {
+            Duration::Height(h) => h,
+            Duration::Time(t) => t,
+        };
+        self.last_disbursed += periods * length;
+    }
 }
 
 #[cw_serde]
@@ -75,36 +295,145 @@ pub struct Escrow{
     //Description
     pub description: String,
 
-    //when end height set and block height exceeds this value, the escrow is expired.
-    //Once an escrow is expired, it can be returned to the original funder(via "refund").
-    pub end_height: Option<u64>,
-
-    // When end time (in seconds since epoch 00:00:00 UTC on 1 January 1970) is set and
-    // block time exceeds this value, the escrow is expired.
+    // Deadlines of the escrow, combined according to expiry_mode.
     // Once an escrow is expired, it can be returned to the original funder (via "refund").
-    pub end_time: Option<u64>,
+    pub expires: Vec<Expiration>,
+
+    // Whether the escrow expires when any or when all of its deadlines passed
+    #[serde(default)]
+    pub expiry_mode: ExpiryMode,
     
     // Balance in Native and Cw20 tokens
     pub balance: GenericBalance,
     
     // All possible contracts that we accept tokens from
     pub cw20_whitelist: Vec<Addr>,
+
+    // When set, the balance is paid out to the stream recipient period by period (via "disburse")
+    pub stream: Option<Stream>,
+
+    // When true, the recipient must accept its role (via "accept_role") before approval
+    #[serde(default)]
+    pub require_acceptance: bool,
+
+    // Whether the current recipient accepted its role
+    #[serde(default)]
+    pub recipient_accepted: bool,
+
+    // When not empty, approval splits the balance between these recipients instead of paying the recipient
+    #[serde(default)]
+    pub splits: Vec<RecipientShare>,
+
+    // Receives what is left over from rounding the splits
+    pub dust_recipient: Option<Addr>,
+
+    // Bounty escrows take submissions (via "submit_claim") while no recipient is set
+    #[serde(default)]
+    pub bounty: bool,
+
+    // Sealed bounties only take submissions as commitments (via "commit_claim") revealed later
+    #[serde(default)]
+    pub sealed: bool,
+
+    // When set, commitments are taken before and revealed after this point
+    pub reveal_start: Option<Expiration>,
+
+    // SHA-256 hash of a secret. When set, anyone presenting the secret (via "release") approves the escrow
+    pub hashlock: Option<Binary>,
+
+    // Swap escrows settle when a counterparty deposits exactly this (via "fill_swap")
+    pub ask: Option<GenericBalance>,
+
+    // When true, the arbiter is a contract that is queried for the outcome (via "resolve")
+    #[serde(default)]
+    pub resolver: bool,
+
+    // Block time of creation, unknown for escrows created before it was recorded
+    pub created_at: Option<Timestamp>,
+
+    // Block height of the latest arbiter action, starting with the creation of the escrow
+    #[serde(default)]
+    pub last_arbiter_action: u64,
+
+    // Blocks without arbiter action after which the arbiter can be replaced
+    pub inactivity_window: Option<u64>,
+
+    // Takes over (via "take_over_arbitration") once the arbiter is inactive
+    pub backup_arbiter: Option<Addr>,
+
+    // New arbiters nominated by the source and the recipient, appointed once both agree
+    pub source_nominee: Option<Addr>,
+    pub recipient_nominee: Option<Addr>,
+
+    // Blocks an arbiter decision stays pending, during which it can be appealed (via "appeal")
+    pub challenge_period: Option<u64>,
+
+    // Decides appeals against the decisions of the arbiter
+    pub appeal_arbiter: Option<Addr>,
+
+    // What an appeal costs, returned if the appeal is upheld and paid to the arbiter otherwise
+    pub appeal_bond: Option<Coin>,
+
+    // Arbiter decision waiting for the challenge period to end or the appeal to be decided
+    pub pending_decision: Option<PendingDecision>,
+
+    // What the source and the recipients agreed to so far (via "mutual_approve" and "mutual_refund")
+    #[serde(default)]
+    pub consents: Vec<Consent>,
+
+    // The source cannot cancel (via "cancel") before this point
+    pub lock_in_until: Option<Expiration>,
+
+    // Approval sends the balance to this account on another chain instead of a local recipient
+    pub ibc_payout: Option<IbcPayout>,
+
+    // Stake of the arbiter held back for this escrow, released when it closes
+    #[serde(default)]
+    pub locked_stake: Uint128,
+}
+
+//A recipient on another chain, reached over one of our ICS-20 channels
+#[cw_serde]
+pub struct IbcPayout {
+    pub channel_id: String,
+    pub receiver: String,
 }
 
 impl Escrow {
     pub fn is_expired(&self, env:&Env)->bool{
-        if let Some(end_height) = self.end_height{
-            if env.block.height > end_height{
-                return true;
-            }
+        if self.expires.is_empty() {
+            return false;
         }
-        if let Some(end_time) = self.end_time{
-            if env.block.time > Timestamp::from_seconds(end_time){
-                return true;
-            }
+        match self.expiry_mode {
+            ExpiryMode::Any => self.expires.iter().any(|e| e.is_expired(&env.block)),
+            ExpiryMode::All => self.expires.iter().all(|e| e.is_expired(&env.block)),
         }
+    }
+
+    //time left until each deadline, zero once it passed
+    pub fn expires_in(&self, env: &Env) -> Vec<Duration> {
+        self.expires
+            .iter()
+            .filter_map(|e| match e {
+                Expiration::AtHeight(h) => Some(Duration::Height(h.saturating_sub(env.block.height))),
+                Expiration::AtTime(t) => Some(Duration::Time(t.seconds().saturating_sub(env.block.time.seconds()))),
+                Expiration::Never {} => None,
+            })
+            .collect()
+    }
 
-        false
+    //whether the arbiter has not acted within the inactivity window
+    pub fn arbiter_inactive(&self, env: &Env) -> bool {
+        self.inactivity_window
+            .is_some_and(|window| env.block.height >= self.last_arbiter_action.saturating_add(window))
+    }
+
+    //the source and everyone the escrow pays out to on approval
+    pub fn parties(&self) -> Vec<Addr> {
+        let mut parties = vec![self.source.clone()];
+        parties.extend(self.recipient.iter().cloned());
+        parties.extend(self.splits.iter().map(|s| s.address.clone()));
+        parties
     }
 
     pub fn human_whitelist(&self)->Vec<String> {
@@ -112,11 +441,582 @@ impl Escrow {
     }
 }
 
+#[cw_serde]
+#[derive(Copy)]
+pub enum Decision {
+    Approve,
+    Refund,
+}
+
+//An arbiter decision that has not been carried out yet
+#[cw_serde]
+pub struct PendingDecision {
+    pub decision: Decision,
+
+    //block height of the decision, the challenge period starts here
+    pub height: u64,
+
+    pub appeal: Option<Appeal>,
+}
+
+//A party's agreement to settle the escrow without the arbiter
+#[cw_serde]
+pub struct Consent {
+    pub party: Addr,
+    pub decision: Decision,
+}
+
+#[cw_serde]
+pub struct Appeal {
+    pub appellant: Addr,
+    pub bond: Vec<Coin>,
+
+    //block height of the appeal, the appeal arbiter has one more challenge period from here
+    #[serde(default)]
+    pub height: u64,
+}
+
 pub const ESCROWS: Map<&str, Escrow> = Map::new("escrow");
 
+//what each funder deposited into an escrow, by escrow id and funder
+pub const CONTRIBUTIONS: Map<(&str, &Addr), GenericBalance> = Map::new("contribution");
+
+#[cw_serde]
+#[derive(Copy, Default)]
+pub enum ExpiryMode {
+    //expired as soon as one deadline passed
+    #[default]
+    Any,
+    //expired once every deadline passed
+    All,
+}
+
+//Escrow as stored before deadlines became expirations, only read when migrating
+#[cw_serde]
+pub struct LegacyEscrow {
+    pub arbiter: Addr,
+    pub recipient: Option<Addr>,
+    pub source: Addr,
+    pub title: String,
+    pub description: String,
+    pub end_height: Option<u64>,
+    pub end_time: Option<u64>,
+    pub balance: GenericBalance,
+    pub cw20_whitelist: Vec<Addr>,
+}
+
+impl From<LegacyEscrow> for Escrow {
+    fn from(legacy: LegacyEscrow) -> Self {
+        //legacy deadlines expired strictly after the given value, expirations at the value itself
+        let mut expires = vec![];
+        if let Some(height) = legacy.end_height {
+            expires.push(Expiration::AtHeight(height + 1));
+        }
+        if let Some(time) = legacy.end_time {
+            expires.push(Expiration::AtTime(Timestamp::from_seconds(time).plus_nanos(1)));
+        }
+        Escrow {
+            arbiter: legacy.arbiter,
+            recipient: legacy.recipient,
+            source: legacy.source,
+            title: legacy.title,
+            description: legacy.description,
+            expires,
+            expiry_mode: ExpiryMode::Any,
+            balance: legacy.balance,
+            cw20_whitelist: legacy.cw20_whitelist,
+            stream: None,
+            require_acceptance: false,
+            recipient_accepted: false,
+            splits: vec![],
+            dust_recipient: None,
+            bounty: false,
+            sealed: false,
+            reveal_start: None,
+            hashlock: None,
+            ask: None,
+            resolver: false,
+            created_at: None,
+            last_arbiter_action: 0,
+            inactivity_window: None,
+            backup_arbiter: None,
+            source_nominee: None,
+            recipient_nominee: None,
+            challenge_period: None,
+            appeal_arbiter: None,
+            appeal_bond: None,
+            pending_decision: None,
+            consents: vec![],
+            lock_in_until: None,
+            ibc_payout: None,
+            locked_stake: Uint128::zero(),
+        }
+    }
+}
+
+pub const LEGACY_ESCROWS: Map<&str, LegacyEscrow> = Map::new("escrow");
+
+//A single field change of an amendment, with the value before and after
+#[cw_serde]
+pub enum Change {
+    Title { old: String, new: String },
+    Description { old: String, new: String },
+    Expires { old: Vec<Expiration>, new: Vec<Expiration> },
+    Cw20Whitelist { old: Vec<Addr>, new: Vec<Addr> },
+}
+
+impl Change {
+    //a later deadline only delays the source's own refund
+    pub fn is_extension(&self) -> bool {
+        match self {
+            Change::Expires { old, new } => {
+                !old.is_empty()
+                    && old.len() == new.len()
+                    && old.iter().all(|o| new.iter().any(|n| n.partial_cmp(o).is_some_and(|ord| ord.is_ge())))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cw_serde]
+pub struct Amendment {
+    pub changes: Vec<Change>,
+
+    //either the source or the arbiter
+    pub proposer: Addr,
+
+    //the other party, None when the source extended its own deadlines
+    pub accepted_by: Option<Addr>,
+
+    //block height of the proposal, or of the acceptance once applied
+    pub height: u64,
+}
+
+//amendments waiting for the other party, at most one per escrow
+pub const PENDING_AMENDMENTS: Map<&str, Amendment> = Map::new("pending_amendment");
+//applied amendments by escrow id and sequence number
+pub const AMENDMENTS: Map<(&str, u64), Amendment> = Map::new("amendment");
+
 //This returns the list of ids for all registered escrows
+//reads the ids only, so escrows still stored in the legacy format are listed as well
 pub fn all_escrow_ids(storage: &dyn Storage) -> StdResult<Vec<String>> {
     ESCROWS
-        .keys(storage, None, None, Order::Ascending)
+        .keys_raw(storage, None, None, Order::Ascending)
+        .map(|key| String::from_utf8(key).map_err(StdError::invalid_utf8))
         .collect()
-}
\ No newline at end of file
+}
+
+//Running totals over all escrows, updated by the handlers so they can be read without going through ESCROWS
+#[cw_serde]
+#[derive(Default)]
+pub struct Stats {
+    pub open: u64,
+    pub created: u64,
+    pub approved: u64,
+    pub refunded: u64,
+    pub locked: GenericBalance,
+    pub donated: GenericBalance,
+}
+
+impl Stats {
+    pub fn lock(&mut self, balance: &GenericBalance) {
+        self.locked.add_tokens(Balance::from(balance.native.clone()));
+        for token in &balance.cw20 {
+            self.locked.add_tokens(Balance::Cw20(token.clone()));
+        }
+    }
+
+    pub fn unlock(&mut self, balance: &GenericBalance) {
+        self.locked.take(balance);
+    }
+}
+
+pub const STATS: Item<Stats> = Item::new("stats");
+
+//Open escrows by deadline (block height or time in nanoseconds), so expired ones are found
+//without going through all escrows
+pub const EXPIRING_AT_HEIGHT: Map<(u64, &str), ()> = Map::new("expiring_at_height");
+pub const EXPIRING_AT_TIME: Map<(u64, &str), ()> = Map::new("expiring_at_time");
+
+pub fn index_deadlines(storage: &mut dyn Storage, id: &str, expires: &[Expiration]) -> StdResult<()> {
+    for expiration in expires {
+        match expiration {
+            Expiration::AtHeight(height) => EXPIRING_AT_HEIGHT.save(storage, (*height, id), &())?,
+            Expiration::AtTime(time) => EXPIRING_AT_TIME.save(storage, (time.nanos(), id), &())?,
+            Expiration::Never {} => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn unindex_deadlines(storage: &mut dyn Storage, id: &str, expires: &[Expiration]) {
+    for expiration in expires {
+        match expiration {
+            Expiration::AtHeight(height) => EXPIRING_AT_HEIGHT.remove(storage, (*height, id)),
+            Expiration::AtTime(time) => EXPIRING_AT_TIME.remove(storage, (time.nanos(), id)),
+            Expiration::Never {} => {}
+        }
+    }
+}
+
+//index entries looked at by one call of take_expired, however few of them turn out to be expired
+const MAX_SCANNED: usize = 100;
+
+//up to limit open escrows that expired in expiry order, those with a passed height deadline first.
+//With ExpiryMode::All one passed deadline is not enough, such deadlines are dropped from the index on
+//the way as the escrow is found again by its last one
+pub fn take_expired(storage: &mut dyn Storage, env: &Env, limit: usize) -> StdResult<Vec<(String, Escrow)>> {
+    let mut expired = vec![];
+    let mut passed_heights = vec![];
+    let mut passed_times = vec![];
+    {
+        let by_height = EXPIRING_AT_HEIGHT
+            .keys(storage, None, None, Order::Ascending)
+            .take_while(|key| key.as_ref().map_or(true, |(height, _)| *height <= env.block.height))
+            .map(|key| key.map(|(height, id)| (Some(height), None, id)));
+        let by_time = EXPIRING_AT_TIME
+            .keys(storage, None, None, Order::Ascending)
+            .take_while(|key| key.as_ref().map_or(true, |(time, _)| *time <= env.block.time.nanos()))
+            .map(|key| key.map(|(time, id)| (None, Some(time), id)));
+        let mut seen = BTreeSet::new();
+        for key in by_height.chain(by_time).take(MAX_SCANNED) {
+            if expired.len() >= limit {
+                break;
+            }
+            let (height, time, id) = key?;
+            let escrow = ESCROWS.load(storage, &id)?;
+            if escrow.is_expired(env) {
+                //escrows with a pending decision wait for it to be carried out
+                if seen.insert(id.clone()) && escrow.pending_decision.is_none() {
+                    expired.push((id, escrow));
+                }
+            } else if let Some(height) = height {
+                passed_heights.push((height, id));
+            } else if let Some(time) = time {
+                passed_times.push((time, id));
+            }
+        }
+    }
+    for (height, id) in &passed_heights {
+        EXPIRING_AT_HEIGHT.remove(storage, (*height, id));
+    }
+    for (time, id) in &passed_times {
+        EXPIRING_AT_TIME.remove(storage, (*time, id));
+    }
+    Ok(expired)
+}
+
+//open escrows past their deadline, counted from the same index entries a sweep would look at, so
+//the count stops at MAX_SCANNED passed deadlines
+pub fn count_expired(storage: &dyn Storage, env: &Env) -> StdResult<u64> {
+    let by_height = EXPIRING_AT_HEIGHT
+        .keys(storage, None, None, Order::Ascending)
+        .take_while(|key| key.as_ref().map_or(true, |(height, _)| *height <= env.block.height))
+        .map(|key| key.map(|(_, id)| id));
+    let by_time = EXPIRING_AT_TIME
+        .keys(storage, None, None, Order::Ascending)
+        .take_while(|key| key.as_ref().map_or(true, |(time, _)| *time <= env.block.time.nanos()))
+        .map(|key| key.map(|(_, id)| id));
+    let mut expired = BTreeSet::new();
+    for id in by_height.chain(by_time).take(MAX_SCANNED) {
+        let id = id?;
+        if !expired.contains(&id) && ESCROWS.load(storage, &id)?.is_expired(env) {
+            expired.insert(id);
+        }
+    }
+    Ok(expired.len() as u64)
+}
+
+//What is kept of an escrow once it is approved or refunded
+#[cw_serde]
+pub struct Settlement {
+    pub outcome: Decision,
+    // The source, the recipients, the arbiter, the other funders and whoever else was paid
+    pub parties: Vec<Addr>,
+    pub payouts: Vec<Payout>,
+    pub height: u64,
+    pub time: Timestamp,
+    // Who sent the message that settled the escrow
+    pub actor: Addr,
+}
+
+#[cw_serde]
+pub struct Payout {
+    // An address, or channel/receiver for payouts over IBC
+    pub to: String,
+    pub amount: GenericBalance,
+}
+
+pub const SETTLED: Map<&str, Settlement> = Map::new("settled");
+
+//Settled escrow ids by party, for the history of a party
+pub const SETTLED_BY_PARTY: Map<(&Addr, &str), ()> = Map::new("settled_by_party");
+
+//Tip paid per escrow to whoever sweeps expired escrows (via "sweep_expired"), set by the owner. It is capped
+//by what the escrow holds of the tip denom, and not paid for escrows of the keeper
+pub const KEEPER_TIP: Item<Coin> = Item::new("keeper_tip");
+
+//Funds the keeper tips are paid from
+pub const KEEPER_RESERVE: Item<GenericBalance> = Item::new("keeper_reserve");
+
+#[cw_serde]
+pub enum SubmissionStatus {
+    //sealed and waiting to be revealed
+    Committed,
+    Open,
+    Rejected,
+    Won,
+}
+
+//A hunter's claim on a bounty escrow
+#[cw_serde]
+pub struct Submission {
+    pub id: u64,
+
+    //receives the bounty if this submission wins
+    pub hunter: Addr,
+
+    //link to or hash of the solution
+    pub link: String,
+
+    pub submitter: Addr,
+
+    //block height of the submission or commitment, earlier ones take priority
+    pub height: u64,
+
+    pub status: SubmissionStatus,
+
+    //sha256 hash of the sealed submission, for commitments
+    pub commitment: Option<Binary>,
+
+    //block height of the reveal, for commitments
+    pub revealed_at: Option<u64>,
+}
+
+//submissions by escrow id and submission id
+pub const SUBMISSIONS: Map<(&str, u64), Submission> = Map::new("submission");
+
+//submissions still waiting for the arbiter by escrow id, commitments count once revealed
+pub const OPEN_SUBMISSIONS: Map<&str, u32> = Map::new("open_submissions");
+
+//submissions by escrow id and submitter, against the per bounty limit
+pub const SUBMISSION_COUNTS: Map<(&str, &Addr), u32> = Map::new("submission_count");
+
+//block height of the latest submission of each address
+pub const LAST_SUBMISSION: Map<&Addr, u64> = Map::new("last_submission");
+
+//Rate limits on bounty submissions, set by the owner
+#[cw_serde]
+#[derive(Default)]
+pub struct SubmissionLimits {
+    //blocks an address has to wait between two submissions
+    pub cooldown_blocks: u64,
+
+    //submissions a single address can make to the same bounty
+    pub max_per_bounty: Option<u32>,
+}
+
+pub const SUBMISSION_LIMITS: Item<SubmissionLimits> = Item::new("submission_limits");
+
+//What an arbiter tells parties about itself when registering
+#[cw_serde]
+pub struct ArbiterProfile {
+    pub name: String,
+
+    //hash of the contact details, which are shared off-chain
+    pub contact_hash: Binary,
+
+    //what the arbiter charges, free text
+    pub fee_terms: String,
+}
+
+pub const ARBITERS: Map<&Addr, ArbiterProfile> = Map::new("arbiters");
+
+//Decisions an arbiter made, kept for registered and unregistered arbiters alike
+#[cw_serde]
+#[derive(Default)]
+pub struct ArbiterStats {
+    pub approvals: u64,
+    pub refunds: u64,
+    pub disputes: u64,
+
+    //decisions on escrows with a known creation time, and the seconds from creation to decision
+    pub timed_decisions: u64,
+    pub total_resolution_time: u64,
+}
+
+impl ArbiterStats {
+    pub fn decisions(&self) -> u64 {
+        self.approvals + self.refunds
+    }
+
+    //average seconds from creation to decision
+    pub fn average_resolution_time(&self) -> Option<u64> {
+        self.total_resolution_time.checked_div(self.timed_decisions)
+    }
+
+    //more decisions rank first, then faster ones. Ranks sort in descending order
+    pub fn rank(&self) -> (u64, u64) {
+        (self.decisions(), u64::MAX - self.average_resolution_time().unwrap_or(u64::MAX))
+    }
+}
+
+pub const ARBITER_STATS: Map<&Addr, ArbiterStats> = Map::new("arbiter_stats");
+
+//registered arbiters by rank, for the ranking without sorting them all
+pub const RANKED_ARBITERS: Map<(u64, u64, &Addr), ()> = Map::new("ranked_arbiters");
+
+//when true, new escrows need a registered arbiter. Set by the owner
+pub const REQUIRE_REGISTERED_ARBITER: Item<bool> = Item::new("require_registered_arbiter");
+
+//Arbiter staking, set by the owner
+#[cw_serde]
+pub struct StakingConfig {
+    //the native denom arbiters stake
+    pub denom: String,
+
+    //stake an arbiter needs, in basis points of the escrow balance in the staking denom
+    pub min_stake_bps: u64,
+
+    //how long unbonded stake stays slashable before it can be claimed
+    pub unbonding_period: Duration,
+
+    //may slash besides the owner
+    pub governance: Option<Addr>,
+}
+
+pub const STAKING_CONFIG: Item<StakingConfig> = Item::new("staking_config");
+
+//bonded stake per arbiter, in the staking denom
+pub const STAKES: Map<&Addr, Uint128> = Map::new("stakes");
+
+//stake per arbiter held back for the escrows it decides, which cannot be unbonded
+pub const LOCKED_STAKES: Map<&Addr, Uint128> = Map::new("locked_stakes");
+
+//all bonded and unbonding stake, the staking denom can only change while there is none
+pub const TOTAL_STAKED: Item<Uint128> = Item::new("total_staked");
+
+//Stake an arbiter can be slashed by on an escrow where an appeal against its decision was upheld
+#[cw_serde]
+pub struct Slashable {
+    pub amount: Uint128,
+
+    //the party that won the appeal, which receives the slashed stake
+    pub appellant: Addr,
+}
+
+//slashable stake by arbiter and escrow id
+pub const SLASHABLE: Map<(&Addr, &str), Slashable> = Map::new("slashable");
+
+//Stake on its way out, which can still be slashed until released
+#[cw_serde]
+pub struct Unbonding {
+    pub amount: Uint128,
+    pub release_at: Expiration,
+}
+
+pub const UNBONDING: Map<&Addr, Vec<Unbonding>> = Map::new("unbonding");
+
+//Defaults for repeat escrows, registered under a name
+#[cw_serde]
+pub struct Template {
+    //can update and remove the template, as can the contract owner
+    pub owner: Addr,
+
+    //starts at 1 and goes up with every update, so creators can pin what they reviewed
+    pub version: u64,
+
+    //the JSON encoded create message, the id is given at creation
+    pub defaults: Binary,
+}
+
+pub const TEMPLATES: Map<&str, Template> = Map::new("templates");
+
+//Funders that came in over IBC through the hooks middleware, by the intermediate account the middleware
+//funded from, with the route back to the sender on the other chain
+pub const REMOTE_FUNDERS: Map<&Addr, IbcPayout> = Map::new("remote_funders");
+
+//A payout or refund on its way to another chain, with who gets the tokens back if it does not arrive
+#[cw_serde]
+pub struct IbcTransfer {
+    pub id: String,
+    pub payout: IbcPayout,
+    pub amount: Coin,
+
+    //split as a refund of the escrow would have been when the transfer was sent
+    pub refunds: Vec<(Addr, GenericBalance)>,
+
+    //set for refunds to a remote funder, which can claim the tokens here if they do not arrive
+    #[serde(default)]
+    pub funder: Option<Addr>,
+}
+
+//Transfers sent in this transaction, waiting for their reply with the packet sequence
+pub const PENDING_IBC_TRANSFERS: Deque<IbcTransfer> = Deque::new("pending_ibc_transfers");
+
+//Transfers sent, by channel and packet sequence, until the middleware reports how they ended
+pub const IBC_TRANSFERS: Map<(&str, u64), IbcTransfer> = Map::new("ibc_transfers");
+
+//Refunds to remote funders that came back, by escrow id and funder, until claimed (via "claim_refund")
+pub const RETURNED_REFUNDS: Map<(&str, &Addr), GenericBalance> = Map::new("returned_refunds");
+
+//Holds the writes of one batch item over the contract storage, so a failed item leaves nothing behind
+pub struct StorageCache<'a> {
+    storage: &'a mut dyn Storage,
+    //None marks a removed key
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> StorageCache<'a> {
+    pub fn new(storage: &'a mut dyn Storage) -> Self {
+        StorageCache { storage, writes: BTreeMap::new() }
+    }
+
+    //applies the cached writes, dropping the cache discards them
+    pub fn commit(self) {
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => self.storage.set(&key, &value),
+                None => self.storage.remove(&key),
+            }
+        }
+    }
+}
+
+impl Storage for StorageCache<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.writes.get(key) {
+            Some(value) => value.clone(),
+            None => self.storage.get(key),
+        }
+    }
+
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'b> {
+        let mut merged: BTreeMap<Vec<u8>, Vec<u8>> = self.storage.range(start, end, Order::Ascending).collect();
+        let in_range = |key: &[u8]| start.is_none_or(|start| key >= start) && end.is_none_or(|end| key < end);
+        for (key, value) in self.writes.iter().filter(|(key, _)| in_range(key)) {
+            match value {
+                Some(value) => merged.insert(key.clone(), value.clone()),
+                None => merged.remove(key),
+            };
+        }
+        match order {
+            Order::Ascending => Box::new(merged.into_iter()),
+            Order::Descending => Box::new(merged.into_iter().rev()),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.writes.insert(key.to_vec(), None);
+    }
+}